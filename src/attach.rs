@@ -0,0 +1,427 @@
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+
+use globset::{GlobBuilder, GlobMatcher};
+use ignore::WalkBuilder;
+
+use crate::template::fence;
+
+/// Maximum number of tokens of all attachments of a single prompt
+pub fn token_budget() -> usize {
+    env::var("RGPT_ATTACH_TOKENS")
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .unwrap_or(25_000)
+}
+
+/// Rough number of tokens of `text` - good enough to stay within a budget
+fn estimate_tokens(text: &str) -> usize {
+    text.len().div_ceil(4)
+}
+
+/// Paths are shown relative to the current directory, without a leading `./`
+fn display_path(path: &Path) -> String {
+    let path = path.strip_prefix(".").unwrap_or(path);
+    path.display().to_string()
+}
+
+/// A file (or a part of it), that was mentioned with `@path` in the prompt
+#[derive(Debug)]
+pub struct Attachment {
+    pub path: String,
+    /// First and last line (starting at 1), if only a part of the file is attached
+    pub lines: Option<(usize, usize)>,
+    pub content: String,
+}
+
+impl Attachment {
+    fn read(path: &str, lines: Option<(usize, usize)>) -> Result<Attachment, String> {
+        let content = fs::read_to_string(path).map_err(|e| match e.kind() {
+            io::ErrorKind::InvalidData => format!("@{path} is not a text file"),
+            _ => format!("Failed to read @{path}: {e}"),
+        })?;
+        let Some((first, last)) = lines else {
+            return Ok(Attachment {
+                path: path.to_string(),
+                lines,
+                content,
+            });
+        };
+        let total = content.lines().count();
+        if first == 0 || first > last || first > total {
+            return Err(format!(
+                "Invalid lines {first}-{last} for @{path}, which has {total} lines"
+            ));
+        }
+        let last = last.min(total);
+        let content: Vec<&str> = content
+            .lines()
+            .skip(first - 1)
+            .take(last - first + 1)
+            .collect();
+        Ok(Attachment {
+            path: path.to_string(),
+            lines: Some((first, last)),
+            content: content.join("\n") + "\n",
+        })
+    }
+
+    /// Short description for the user, e.g. `src/client.rs (lines 40-80)`
+    pub fn describe(&self) -> String {
+        match self.lines {
+            Some((first, last)) => format!("{} (lines {first}-{last})", self.path),
+            None => format!("{} ({} lines)", self.path, self.content.lines().count()),
+        }
+    }
+
+    fn to_markdown(&self) -> String {
+        let title = match self.lines {
+            Some((first, last)) => format!("`{}` (lines {first}-{last})", self.path),
+            None => format!("`{}`", self.path),
+        };
+        format!(
+            "{title}:\n\n{}",
+            fence(Path::new(&self.path), &self.content)
+        )
+    }
+}
+
+/// A file that was found in a directory or by a glob
+#[derive(Debug)]
+enum Entry {
+    Attached(Attachment),
+    /// Didn't fit into the token budget
+    Omitted(String),
+    Binary(String),
+}
+
+impl Entry {
+    fn path(&self) -> &str {
+        match self {
+            Entry::Attached(attachment) => &attachment.path,
+            Entry::Omitted(path) | Entry::Binary(path) => path,
+        }
+    }
+}
+
+/// All files of a directory or glob, that was mentioned with `@src/` or `@src/**/*.rs`
+#[derive(Debug)]
+pub struct Tree {
+    pub pattern: String,
+    entries: Vec<Entry>,
+}
+
+impl Tree {
+    /// Walks `base` (respecting .gitignore), and keeps the files that match `glob`
+    fn walk(pattern: &str, base: &Path, glob: Option<&GlobMatcher>) -> Result<Tree, String> {
+        let mut paths = Vec::new();
+        for entry in WalkBuilder::new(base).require_git(false).build() {
+            let entry = entry.map_err(|e| format!("Failed to read @{pattern}: {e}"))?;
+            if !entry.file_type().is_some_and(|t| t.is_file()) {
+                continue;
+            }
+            let path = display_path(entry.path());
+            if glob.is_none_or(|glob| glob.is_match(&path)) {
+                paths.push(path);
+            }
+        }
+        paths.sort();
+        if paths.is_empty() {
+            return Err(format!("@{pattern} doesn't contain any files"));
+        }
+        let entries = paths
+            .into_iter()
+            .map(|path| match fs::read(&path) {
+                // Text files are attached later, if they fit into the budget
+                Ok(bytes) if !bytes.contains(&0) => match String::from_utf8(bytes) {
+                    Ok(content) => Entry::Attached(Attachment {
+                        path,
+                        lines: None,
+                        content,
+                    }),
+                    Err(_) => Entry::Binary(path),
+                },
+                _ => Entry::Binary(path),
+            })
+            .collect();
+        Ok(Tree {
+            pattern: pattern.to_string(),
+            entries,
+        })
+    }
+
+    fn attached(&self) -> impl Iterator<Item = &Attachment> {
+        self.entries.iter().filter_map(|entry| match entry {
+            Entry::Attached(attachment) => Some(attachment),
+            _ => None,
+        })
+    }
+
+    /// Attaches files in order as long as they fit into the `budget`, the others are omitted.
+    ///
+    /// Returns the tokens that were used.
+    fn fit(&mut self, budget: usize) -> usize {
+        let mut used = 0;
+        for entry in self.entries.iter_mut() {
+            let Entry::Attached(attachment) = entry else {
+                continue;
+            };
+            let tokens = estimate_tokens(&attachment.content);
+            if used + tokens > budget {
+                *entry = Entry::Omitted(attachment.path.clone());
+            } else {
+                used += tokens;
+            }
+        }
+        used
+    }
+
+    /// Short description for the user, e.g. `src/ (12 files, 2 omitted, 1 binary skipped)`
+    pub fn describe(&self) -> String {
+        let count = |f: fn(&Entry) -> bool| self.entries.iter().filter(|e| f(e)).count();
+        let mut description = format!(
+            "{} ({} files",
+            self.pattern,
+            count(|e| matches!(e, Entry::Attached(_)))
+        );
+        let omitted = count(|e| matches!(e, Entry::Omitted(_)));
+        if omitted > 0 {
+            description.push_str(&format!(
+                ", {omitted} omitted by the token budget (see RGPT_ATTACH_TOKENS)"
+            ));
+        }
+        let binary = count(|e| matches!(e, Entry::Binary(_)));
+        if binary > 0 {
+            description.push_str(&format!(", {binary} binary skipped"));
+        }
+        description.push(')');
+        description
+    }
+
+    /// An indented tree of all files, followed by the contents of the attached ones
+    fn to_markdown(&self) -> String {
+        let mut tree = String::new();
+        let mut last: Vec<&str> = Vec::new();
+        for entry in self.entries.iter() {
+            let components: Vec<&str> = entry.path().split('/').collect();
+            let (file, dirs) = components.split_last().unwrap_or((&"", &[]));
+            // Only print the directories that differ from the previous file
+            let common = last.iter().zip(dirs).take_while(|(a, b)| a == b).count();
+            for (depth, dir) in dirs.iter().enumerate().skip(common) {
+                tree.push_str(&format!("{}{dir}/\n", "  ".repeat(depth)));
+            }
+            let note = match entry {
+                Entry::Attached(_) => "",
+                Entry::Omitted(_) => " (omitted)",
+                Entry::Binary(_) => " (binary)",
+            };
+            tree.push_str(&format!("{}{file}{note}\n", "  ".repeat(dirs.len())));
+            last = dirs.to_vec();
+        }
+        let mut markdown = format!(
+            "Files in `{}`:\n\n{}",
+            self.pattern,
+            fence(Path::new(""), &tree)
+        );
+        for attachment in self.attached() {
+            markdown.push_str("\n\n");
+            markdown.push_str(&attachment.to_markdown());
+        }
+        markdown
+    }
+}
+
+/// Everything that can be mentioned with `@`
+#[derive(Debug)]
+pub enum Attached {
+    File(Attachment),
+    Tree(Tree),
+}
+
+impl Attached {
+    pub fn describe(&self) -> String {
+        match self {
+            Attached::File(attachment) => attachment.describe(),
+            Attached::Tree(tree) => tree.describe(),
+        }
+    }
+
+    fn to_markdown(&self) -> String {
+        match self {
+            Attached::File(attachment) => attachment.to_markdown(),
+            Attached::Tree(tree) => tree.to_markdown(),
+        }
+    }
+}
+
+/// What a single `@` mention refers to
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Mention<'a> {
+    File(&'a str, Option<(usize, usize)>),
+    Dir(&'a str),
+    Glob(&'a str),
+}
+
+/// Splits a mention like `src/client.rs:40-80` into the path and the line range
+fn parse_mention(mention: &str) -> (&str, Option<(usize, usize)>) {
+    let Some((path, range)) = mention.rsplit_once(':') else {
+        return (mention, None);
+    };
+    let lines = match range.split_once('-') {
+        Some((first, last)) => first.parse().ok().zip(last.parse().ok()),
+        None => range.parse().ok().map(|line| (line, line)),
+    };
+    match lines {
+        Some(lines) => (path, Some(lines)),
+        None => (mention, None),
+    }
+}
+
+/// Characters that end a sentence rather than a mention
+const PUNCTUATION: [char; 9] = [',', '.', ';', ':', '!', '?', ')', '\'', '"'];
+
+/// Finds what a word like `@src/main.rs,` refers to (ignoring trailing punctuation)
+fn find_mention(word: &str) -> Option<Mention<'_>> {
+    let word = word.strip_prefix('@')?;
+    let mut mention = word;
+    loop {
+        let (path, lines) = parse_mention(mention);
+        if !path.is_empty() && Path::new(path).is_file() {
+            return Some(Mention::File(path, lines));
+        }
+        if lines.is_none() && !mention.is_empty() && Path::new(mention).is_dir() {
+            return Some(Mention::Dir(mention));
+        }
+        let Some(rest) = mention.strip_suffix(PUNCTUATION) else {
+            break;
+        };
+        mention = rest;
+    }
+    // Only a mention of nothing that exists is a glob, where a trailing `?` ends the question
+    let glob = word.trim_end_matches(PUNCTUATION);
+    (glob.contains(['*', '?', '[']) && glob.contains(['/', '.'])).then_some(Mention::Glob(glob))
+}
+
+/// The directory a glob starts in - everything before the first component with a wildcard
+fn glob_base(pattern: &str) -> PathBuf {
+    let mut base = PathBuf::new();
+    for component in Path::new(pattern).components() {
+        match component {
+            Component::Normal(part) if part.to_string_lossy().contains(['*', '?', '[']) => break,
+            component => base.push(component),
+        }
+    }
+    if base.as_os_str().is_empty() || base == Path::new(pattern) {
+        PathBuf::from(".")
+    } else {
+        base
+    }
+}
+
+fn read_mention(mention: Mention) -> Result<Attached, String> {
+    match mention {
+        Mention::File(path, lines) => Attachment::read(path, lines).map(Attached::File),
+        Mention::Dir(dir) => Tree::walk(dir, Path::new(dir), None).map(Attached::Tree),
+        Mention::Glob(pattern) => {
+            let pattern = pattern.strip_prefix("./").unwrap_or(pattern);
+            let glob = GlobBuilder::new(pattern)
+                .literal_separator(true)
+                .build()
+                .map_err(|e| format!("Invalid pattern @{pattern}: {e}"))?
+                .compile_matcher();
+            Tree::walk(pattern, &glob_base(pattern), Some(&glob)).map(Attached::Tree)
+        }
+    }
+}
+
+/// Appends all files, directories and globs that are mentioned with `@` to the prompt.
+///
+/// Single files have to fit into the token budget, directories and globs
+/// fill what is left of it. Returns the new prompt, and what was attached.
+pub fn attach(input: &str) -> Result<(String, Vec<Attached>), String> {
+    let mut attached: Vec<Attached> = Vec::new();
+    let mut seen = Vec::new();
+    for word in input.split_whitespace() {
+        let Some(mention) = find_mention(word) else {
+            continue;
+        };
+        if seen.contains(&mention) {
+            continue;
+        }
+        seen.push(mention);
+        attached.push(read_mention(mention)?);
+    }
+    let budget = token_budget();
+    let files: usize = attached
+        .iter()
+        .filter_map(|a| match a {
+            Attached::File(attachment) => Some(estimate_tokens(&attachment.content)),
+            Attached::Tree(_) => None,
+        })
+        .sum();
+    if files > budget {
+        return Err(format!(
+            "The attached files have about {files} tokens, but only {budget} are allowed (see RGPT_ATTACH_TOKENS)"
+        ));
+    }
+    let mut remaining = budget - files;
+    for attachment in attached.iter_mut() {
+        if let Attached::Tree(tree) = attachment {
+            remaining -= tree.fit(remaining);
+        }
+    }
+    let mut prompt = input.to_string();
+    for attachment in attached.iter() {
+        prompt.push_str("\n\n");
+        prompt.push_str(&attachment.to_markdown());
+    }
+    Ok((prompt, attached))
+}
+
+#[test]
+fn test_parse_mention() {
+    assert_eq!(
+        parse_mention("src/client.rs:40-80"),
+        ("src/client.rs", Some((40, 80)))
+    );
+    assert_eq!(
+        parse_mention("src/main.rs:7"),
+        ("src/main.rs", Some((7, 7)))
+    );
+    assert_eq!(parse_mention("src/main.rs"), ("src/main.rs", None));
+    assert_eq!(parse_mention("c:/x:y"), ("c:/x:y", None));
+    assert_eq!(
+        find_mention("@Cargo.toml:1-2,"),
+        Some(Mention::File("Cargo.toml", Some((1, 2))))
+    );
+    assert_eq!(find_mention("@src/"), Some(Mention::Dir("src/")));
+    assert_eq!(
+        find_mention("@src/**/*.rs,"),
+        Some(Mention::Glob("src/**/*.rs"))
+    );
+    assert_eq!(
+        find_mention("@src/main.rs?"),
+        Some(Mention::File("src/main.rs", None))
+    );
+    assert_eq!(
+        find_mention("@Cargo.toml?"),
+        Some(Mention::File("Cargo.toml", None))
+    );
+    assert_eq!(find_mention("@src/*.rs?"), Some(Mention::Glob("src/*.rs")));
+    assert_eq!(find_mention("@missing.rs?"), None);
+    assert_eq!(find_mention("@user"), None);
+}
+
+#[test]
+fn test_attach_glob() {
+    let (_, attached) = attach("Explain @src/**/*.rs").unwrap();
+    let Attached::Tree(tree) = &attached[0] else {
+        panic!("expected a tree, got {attached:?}");
+    };
+    assert!(tree.attached().any(|a| a.path == "src/attach.rs"));
+    assert!(tree.entries.iter().all(|e| e.path().ends_with(".rs")));
+    assert_eq!(glob_base("src/**/*.rs"), Path::new("src"));
+    assert_eq!(glob_base("*.rs"), Path::new("."));
+}
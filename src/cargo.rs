@@ -0,0 +1,301 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+
+use serde::Deserialize;
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::process::Command;
+
+use crate::client::GptClient;
+use crate::template::fence;
+use crate::MdPrinter;
+
+/// Maximum number of diagnostics we ask about in a single run (errors come first)
+const MAX_DIAGNOSTICS: usize = 10;
+
+/// The cargo commands we explain the diagnostics of
+const COMMANDS: [&str; 4] = ["check", "build", "test", "clippy"];
+
+/// Lines of source code that are shown around a diagnostic
+const SOURCE_CONTEXT: usize = 3;
+
+/// A single line of `cargo --message-format=json`
+#[derive(Debug, Deserialize)]
+struct CargoMessage {
+    reason: String,
+    message: Option<Diagnostic>,
+}
+
+/// The part of `cargo metadata` we need
+#[derive(Debug, Deserialize)]
+struct Metadata {
+    workspace_root: PathBuf,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct Diagnostic {
+    message: String,
+    level: String,
+    code: Option<Code>,
+    #[serde(default)]
+    spans: Vec<Span>,
+    /// The message as the compiler prints it
+    rendered: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct Code {
+    code: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct Span {
+    file_name: String,
+    line_start: usize,
+    line_end: usize,
+    is_primary: bool,
+}
+
+impl Diagnostic {
+    fn primary_span(&self) -> Option<&Span> {
+        self.spans.iter().find(|s| s.is_primary)
+    }
+
+    /// Short description, e.g. `error[E0308]: mismatched types (line 12)`
+    fn summary(&self) -> String {
+        let code = self
+            .code
+            .as_ref()
+            .map(|c| format!("[{}]", c.code))
+            .unwrap_or_default();
+        let line = self
+            .primary_span()
+            .map(|s| format!(" (line {})", s.line_start))
+            .unwrap_or_default();
+        format!("{}{code}: {}{line}", self.level, self.message)
+    }
+}
+
+/// Parses a line of cargo's stdout - test binaries print their own output in between
+fn parse_message(line: &str) -> Option<CargoMessage> {
+    serde_json::from_str(line).ok()
+}
+
+/// The root of the workspace - cargo reports the files of diagnostics relative to it
+async fn workspace_root() -> Option<PathBuf> {
+    let output = Command::new("cargo")
+        .args(["metadata", "--format-version", "1", "--no-deps"])
+        .stderr(Stdio::inherit())
+        .output()
+        .await
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let metadata: Metadata = serde_json::from_slice(&output.stdout).ok()?;
+    Some(metadata.workspace_root)
+}
+
+/// Parses the output of cargo - only the errors and warnings, that point to a file
+fn parse_diagnostics(output: &str) -> Vec<Diagnostic> {
+    let mut diagnostics: Vec<Diagnostic> = Vec::new();
+    for line in output.lines() {
+        let Some(message) = parse_message(line) else {
+            continue;
+        };
+        let Some(diagnostic) = message.message else {
+            continue;
+        };
+        if message.reason != "compiler-message"
+            || !matches!(diagnostic.level.as_str(), "error" | "warning")
+            || diagnostic.primary_span().is_none()
+        {
+            continue;
+        }
+        // The same diagnostic is reported for every target that includes the file
+        if diagnostics
+            .iter()
+            .any(|d| d.rendered == diagnostic.rendered)
+        {
+            continue;
+        }
+        diagnostics.push(diagnostic);
+    }
+    diagnostics
+}
+
+/// The source lines of the span (and some context), with line numbers
+fn source_excerpt(root: &Path, span: &Span) -> Option<String> {
+    let content = fs::read_to_string(root.join(&span.file_name)).ok()?;
+    let first = span.line_start.saturating_sub(SOURCE_CONTEXT).max(1);
+    let last = span.line_end + SOURCE_CONTEXT;
+    let excerpt: Vec<String> = content
+        .lines()
+        .enumerate()
+        .map(|(i, line)| (i + 1, line))
+        .filter(|(n, _)| (first..=last).contains(n))
+        .map(|(n, line)| format!("{n:>4} | {line}"))
+        .collect();
+    let excerpt = excerpt.join("\n");
+    Some(format!(
+        "Source of `{}`:\n\n{}",
+        span.file_name,
+        fence(Path::new(&span.file_name), &excerpt)
+    ))
+}
+
+/// The question for the model about a single diagnostic
+fn question(root: &Path, command: &str, diagnostic: &Diagnostic) -> String {
+    let rendered = diagnostic
+        .rendered
+        .clone()
+        .unwrap_or_else(|| diagnostic.message.clone());
+    let mut question = format!(
+        "Explain this {} from `cargo {command}` and show how to fix it:\n\n{}",
+        diagnostic.level,
+        fence(Path::new(""), &rendered)
+    );
+    if let Some(excerpt) = diagnostic
+        .primary_span()
+        .and_then(|span| source_excerpt(root, span))
+    {
+        question.push_str("\n\n");
+        question.push_str(&excerpt);
+    }
+    question
+}
+
+/// Whether `args` (everything after `rgpt cargo`) are meant for cargo - options have to follow
+/// the command directly, so `rgpt cargo build fails, why?` is still a question
+pub fn is_invocation(args: &[&str]) -> bool {
+    match args {
+        [] => true,
+        [command, rest @ ..] => {
+            COMMANDS.contains(command) && rest.first().is_none_or(|arg| arg.starts_with('-'))
+        }
+    }
+}
+
+/// Runs `rgpt cargo [check|build|test|clippy] [args]`
+pub async fn run(
+    args: &[String],
+    client: &GptClient,
+    md: &MdPrinter,
+) -> Result<(), Box<dyn Error>> {
+    let (command, extra) = match args.split_first() {
+        None => ("check", &[][..]),
+        Some((command, extra)) if COMMANDS.contains(&command.as_str()) => (command.as_str(), extra),
+        Some(_) => return Err("Usage: rgpt cargo [check|build|test|clippy] [args]".into()),
+    };
+    // Without the workspace root we can still ask, just without the source
+    let root = workspace_root().await.unwrap_or_default();
+
+    // Cargo shows its progress on stderr, the diagnostics come as json on stdout -
+    // together with the output of the tests, which we show as it arrives
+    let mut child = Command::new("cargo")
+        .arg(command)
+        .arg("--message-format=json")
+        .args(extra)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::inherit())
+        .kill_on_drop(true)
+        .spawn()?;
+    let stdout = child
+        .stdout
+        .take()
+        .ok_or("the output of cargo is not piped")?;
+    let mut lines = BufReader::new(stdout).lines();
+    let mut messages = String::new();
+    while let Some(line) = lines.next_line().await? {
+        if parse_message(&line).is_some() {
+            messages.push_str(&line);
+            messages.push('\n');
+        } else {
+            println!("{line}");
+        }
+    }
+    let status = child.wait().await?;
+    let failed = (!status.success()).then(|| {
+        status
+            .code()
+            .map(|code| format!("exit status {code}"))
+            .unwrap_or_else(|| "a signal".to_string())
+    });
+
+    let mut diagnostics = parse_diagnostics(&messages);
+    if diagnostics.is_empty() {
+        match failed {
+            Some(status) => println!(
+                "--- System: No errors or warnings, but `cargo {command}` failed with {status}"
+            ),
+            None => println!("--- System: No errors or warnings - `cargo {command}` succeeded"),
+        }
+        return Ok(());
+    }
+    if let Some(status) = &failed {
+        println!("--- System: `cargo {command}` failed with {status}");
+    }
+
+    // Errors first, and only as many as we want to pay for
+    diagnostics.sort_by_key(|d| d.level != "error");
+    let skipped = diagnostics.len().saturating_sub(MAX_DIAGNOSTICS);
+    diagnostics.truncate(MAX_DIAGNOSTICS);
+    let mut by_file: BTreeMap<String, Vec<Diagnostic>> = BTreeMap::new();
+    for diagnostic in diagnostics {
+        let file = diagnostic
+            .primary_span()
+            .map(|s| s.file_name.clone())
+            .unwrap_or_default();
+        by_file.entry(file).or_default().push(diagnostic);
+    }
+
+    for (file, diagnostics) in by_file.iter_mut() {
+        diagnostics.sort_by_key(|d| d.primary_span().map(|s| s.line_start));
+        println!();
+        md.print(format!("# {file}"))?;
+        for diagnostic in diagnostics.iter() {
+            println!();
+            md.print(format!("## {}", diagnostic.summary()))?;
+            match client.ask(&question(&root, command, diagnostic)).await {
+                Ok(answer) => md.print(answer)?,
+                Err(e) => println!("--- System: {e}"),
+            }
+        }
+    }
+    if skipped > 0 {
+        println!();
+        println!("--- System: {skipped} more diagnostics were not explained - fix these first and run again");
+    }
+    Ok(())
+}
+
+#[test]
+fn test_is_invocation() {
+    assert!(is_invocation(&[]));
+    assert!(is_invocation(&["clippy", "--all-targets"]));
+    assert!(is_invocation(&["test", "--", "my_test"]));
+    assert!(!is_invocation(&["build", "fails,", "why?"]));
+    assert!(!is_invocation(&["workspaces", "explained"]));
+}
+
+#[test]
+fn test_parse_diagnostics() {
+    let error = r#"{"reason":"compiler-message","package_id":"rgpt","target":{},"message":{"rendered":"error[E0308]: mismatched types\n","$message_type":"diagnostic","children":[],"code":{"code":"E0308","explanation":null},"level":"error","message":"mismatched types","spans":[{"byte_end":10,"byte_start":5,"column_end":10,"column_start":5,"expansion":null,"file_name":"src/main.rs","is_primary":true,"label":"expected `u32`","line_end":12,"line_start":12,"suggested_replacement":null,"suggestion_applicability":null,"text":[]}]}}"#;
+    let summary = r#"{"reason":"compiler-message","package_id":"rgpt","target":{},"message":{"rendered":"error: aborting due to 1 previous error\n","children":[],"code":null,"level":"error","message":"aborting due to 1 previous error","spans":[]}}"#;
+    let output = [
+        error,
+        error,
+        summary,
+        "test foo ... ok",
+        r#"{"reason":"build-finished","success":false}"#,
+    ]
+    .join("\n");
+    let diagnostics = parse_diagnostics(&output);
+    assert_eq!(diagnostics.len(), 1);
+    assert_eq!(
+        diagnostics[0].summary(),
+        "error[E0308]: mismatched types (line 12)"
+    );
+}
@@ -21,13 +21,22 @@
 //         }'
 
 use std::env::{self, VarError};
+use std::time::Duration;
 
-use eventsource_stream::Eventsource;
+use chrono::{DateTime, Utc};
+use eventsource_stream::{EventStreamError, Eventsource};
+use futures::future::join_all;
 use futures::StreamExt;
-use reqwest::Client;
+use reqwest::{Client, RequestBuilder};
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
-use tokio::sync::mpsc;
+use tokio::sync::{mpsc, oneshot};
+use tokio::time::{timeout, timeout_at, Instant};
+
+use crate::config::Project;
+use crate::facts;
+use crate::persona::Persona;
+use crate::session::Session;
 
 // Until we define our error-type
 type Result<T> = std::result::Result<T, Error>;
@@ -40,23 +49,80 @@ pub enum Error {
     Send(#[from] mpsc::error::SendError<Output>),
     #[error("Missing OPENAI_KEY environment variable")]
     ApiKey(#[from] VarError),
+    #[error(transparent)]
+    Stream(#[from] EventStreamError<reqwest::Error>),
+    #[error("Failed to save session: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("No answer within {0:?} - aborting request")]
+    FirstToken(Duration),
+    #[error("Answer stalled for more than {0:?} - aborting request")]
+    Idle(Duration),
 }
 
+/// A single message of the conversation
 #[derive(Debug, Clone, Serialize, Deserialize)]
-struct Msg {
+pub struct Msg {
+    pub role: String,
+    pub content: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<DateTime<Utc>>,
+    /// Pinned messages survive clearing and truncating the conversation
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub pinned: bool,
+}
+
+impl Msg {
+    pub fn new(role: &str, content: String) -> Self {
+        Msg {
+            role: role.to_string(),
+            content,
+            timestamp: Some(Utc::now()),
+            pinned: false,
+        }
+    }
+}
+
+/// A message as it is sent to the api (which rejects any additional fields)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct ReqMsg {
     role: String,
     content: String,
 }
 
+impl From<&Msg> for ReqMsg {
+    fn from(msg: &Msg) -> Self {
+        ReqMsg {
+            role: msg.role.clone(),
+            content: msg.content.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct StreamOptions {
+    include_usage: bool,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 struct GptReq {
     model: String,
-    messages: Vec<Msg>,
+    messages: Vec<ReqMsg>,
     stream: bool,
-    // max_tokens: usize,
-    // temperature: f32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stream_options: Option<StreamOptions>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_tokens: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    logprobs: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    top_logprobs: Option<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    n: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    temperature: Option<f32>,
 }
 
+#[allow(dead_code)]
 #[derive(Debug, Clone, Serialize, Deserialize)]
 struct Choice {
     index: i64,
@@ -64,13 +130,22 @@ struct Choice {
     finish_reason: String,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-struct Usage {
-    prompt_tokens: u64,
-    completion_tokens: u64,
-    total_tokens: u64,
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Usage {
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+    pub total_tokens: u64,
 }
 
+impl Usage {
+    pub fn add(&mut self, other: &Usage) {
+        self.prompt_tokens += other.prompt_tokens;
+        self.completion_tokens += other.completion_tokens;
+        self.total_tokens += other.total_tokens;
+    }
+}
+
+#[allow(dead_code)]
 #[derive(Debug, Clone, Serialize, Deserialize)]
 struct GptRes {
     id: String,
@@ -89,6 +164,9 @@ struct Chunk {
     model: String,
     system_fingerprint: Option<String>,
     choices: Vec<ChunkChoice>,
+    /// Only part of the last chunk, if requested via `stream_options`
+    #[serde(default)]
+    usage: Option<Usage>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -96,6 +174,34 @@ struct ChunkChoice {
     index: usize,
     delta: DeltaMsg,
     finish_reason: Option<String>,
+    #[serde(default)]
+    logprobs: Option<ChunkLogprobs>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct ChunkLogprobs {
+    content: Option<Vec<TokenLogprob>>,
+}
+
+/// A single token of an answer, together with the alternatives the model considered
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TokenLogprob {
+    pub token: String,
+    pub logprob: f64,
+    #[serde(default)]
+    pub top_logprobs: Vec<TopLogprob>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TopLogprob {
+    pub token: String,
+    pub logprob: f64,
+}
+
+impl TokenLogprob {
+    pub fn probability(&self) -> f64 {
+        self.logprob.exp()
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -106,7 +212,32 @@ struct DeltaMsg {
 
 pub struct GptClient {
     client: Client,
-    messages: Vec<Msg>,
+    /// The current conversation, which is saved after every answer
+    session: Session,
+    /// The active system prompt and its settings
+    persona: Persona,
+    /// Settings of the repository we are working in
+    project: Option<Project>,
+    /// Maximum time we wait for the first token of an answer
+    first_token_timeout: Duration,
+    /// Maximum time we wait between two chunks of an answer
+    idle_timeout: Duration,
+    /// Upper limit for the length of a single answer
+    max_tokens: Option<usize>,
+    /// Automatically request the rest of an answer that was cut off
+    auto_continue: bool,
+    /// Request the probabilities of each token of the answer
+    logprobs: bool,
+    /// Number of answers that are requested for each question
+    choices: usize,
+    /// All answers to the last question, until the user picked one of them
+    alternatives: Vec<String>,
+    /// Models that answer every question side by side
+    compare: Vec<String>,
+    /// Where a new system prompt is put into the conversation
+    switch: Switch,
+    /// Id of the last session we requested a title for
+    title_requested: String,
 }
 
 #[test]
@@ -116,140 +247,815 @@ fn test_chunk() {
     assert!(parsed.is_ok(), "Error: {}", parsed.unwrap_err());
 }
 
-#[derive(Debug)]
-pub enum UseContext {
-    Basic,
-    Short,
-    Programming,
+#[test]
+fn test_chunk_logprobs() {
+    let msg = r#"{"id":"chatcmpl-AbC","object":"chat.completion.chunk","created":1736500000,"model":"gpt-4o-2024-08-06","system_fingerprint":"fp_1","choices":[{"index":0,"delta":{"content":"Hi"},"logprobs":{"content":[{"token":"Hi","logprob":-0.01,"bytes":[72,105],"top_logprobs":[{"token":"Hi","logprob":-0.01,"bytes":[72,105]},{"token":"Hello","logprob":-4.6,"bytes":[72,101,108,108,111]}]}],"refusal":null},"finish_reason":null}]}"#;
+    let parsed: Chunk = serde_json::from_str(msg).unwrap();
+    let tokens = parsed.choices[0].logprobs.clone().unwrap().content.unwrap();
+    assert_eq!(tokens[0].token, "Hi");
+    assert_eq!(tokens[0].top_logprobs.len(), 2);
+    assert!(tokens[0].probability() > 0.98);
+}
+
+/// How a new system prompt is applied to the conversation
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Switch {
+    /// The prompt replaces the old one at the start of the conversation
+    Retroactive,
+    /// The prompt moves to the end of the conversation, so it only governs the following answers
+    FromNowOn,
 }
 
 #[derive(Debug)]
 pub enum Input {
     Text(String),
-    Context(UseContext),
+    /// Switch to another system prompt (and its settings)
+    Persona(Persona),
+    /// Use an ad-hoc system prompt, but keep the settings of the persona
+    System(String),
+    /// Change how the system prompt is replaced on a switch
+    Switch(Switch),
+    Logprobs(bool),
+    /// Number of answers that are requested for each question
+    Choices(usize),
+    /// Keep the answer with the given index, and discard the alternatives
+    Pick(usize),
+    /// Replace the current conversation with a stored session
+    Load(Box<Session>),
+    /// Request a copy of the current conversation
+    Snapshot(oneshot::Sender<Session>),
+    /// Forget everything after the first `n` messages of the conversation (except pinned ones)
+    Truncate(usize),
+    /// Pin (or unpin) the message with the given index
+    Pin(usize, bool),
+    /// Continue in a copy of the current session, and send back that copy
+    Fork(oneshot::Sender<Session>),
+    /// Send every question to all of these models (or just the default one, if empty)
+    Compare(Vec<String>),
+    Continue,
     Clear,
 }
 
+/// The answer of a single model, when comparing several models
+#[derive(Debug)]
+pub struct Comparison {
+    pub model: String,
+    pub answer: std::result::Result<String, String>,
+    pub latency: Duration,
+    pub usage: Option<Usage>,
+}
+
 #[derive(Debug)]
 pub enum Output {
     Data(String),
+    /// Part of an alternative answer (only if more than one answer was requested)
+    Alternative(usize, String),
+    Tokens(Vec<TokenLogprob>),
+    /// Answers of several models to the same question
+    Comparison(Vec<Comparison>),
+    Info(String),
+    Error(String),
     End,
 }
 
-const BASIC_CONTEXT: &str = "You are a helpful assistant.";
-const NO_REPEAT: &str = "You are a helpful and very direct assistant.\
-                         You don't repeat the user's input in your answer,\
-                         you just provide a short and precise answer.";
-const PROGRAMMING: &str = "You are an assistant for a programmer.\
-                           You can assume basic knowledge about how to use the commandline in linux \
-                           and an understanding of basic principles in programming languages.\
-                           In general, all your answers should assume, that the user is running a linux operating system.\
-                           However, this should not change your answer related to non-computer issues.";
+const CONTINUE: &str = "Your last answer was cut off. \
+                        Continue it exactly where it stopped, without repeating anything.";
+
+const TITLE: &str = "Summarize the topic of the following conversation as a short title \
+                     of at most six words. Answer only with the title.";
+
+/// How often we continue an answer automatically, before we ask the user to do it
+const MAX_AUTO_CONTINUE: usize = 3;
+
+/// Number of alternatives we request for each token, if logprobs are enabled
+const TOP_LOGPROBS: u8 = 5;
+
+/// Reads a timeout (in seconds) from the given environment variable
+fn timeout_from_env(var: &str, default_secs: u64) -> Duration {
+    match env::var(var).map(|s| s.parse::<f64>()) {
+        Ok(Ok(secs)) if secs > 0.0 => Duration::from_secs_f64(secs),
+        Ok(_) => {
+            eprintln!("--- System: Invalid value for {var}, using {default_secs}s");
+            Duration::from_secs(default_secs)
+        }
+        Err(_) => Duration::from_secs(default_secs),
+    }
+}
+
+/// Model that is used for the conversation (environment variable OPENAI_MODEL)
+fn default_model() -> String {
+    env::var("OPENAI_MODEL").unwrap_or_else(|_| "gpt-4o".to_string())
+}
+
+/// Whether the user wants to see token probabilities (environment variable RGPT_LOGPROBS)
+pub fn logprobs_from_env() -> bool {
+    env::var("RGPT_LOGPROBS").is_ok_and(|s| s == "1" || s == "true")
+}
+
+/// Prepares a request to the chat completions endpoint
+fn chat_request(client: &Client, rq: &GptReq) -> Result<RequestBuilder> {
+    let openai_key = env::var("OPENAI_KEY")?;
+    Ok(client
+        .post("https://api.openai.com/v1/chat/completions")
+        .bearer_auth(openai_key)
+        .json(rq))
+}
+
+/// Asks a cheap model (environment variable RGPT_TITLE_MODEL) for a short title of the conversation
+async fn generate_title(client: &Client, conversation: Vec<ReqMsg>) -> Option<String> {
+    let mut messages = vec![ReqMsg {
+        role: "system".to_string(),
+        content: TITLE.to_string(),
+    }];
+    messages.extend(conversation);
+    let rq = GptReq {
+        model: env::var("RGPT_TITLE_MODEL").unwrap_or_else(|_| "gpt-4o-mini".to_string()),
+        messages,
+        stream: false,
+        stream_options: None,
+        max_tokens: Some(20),
+        logprobs: None,
+        top_logprobs: None,
+        n: None,
+        temperature: None,
+    };
+    let response = chat_request(client, &rq).ok()?.send().await.ok()?;
+    let res: GptRes = response.error_for_status().ok()?.json().await.ok()?;
+    let title = res.choices.into_iter().next()?.message.content;
+    let title = title.trim().trim_matches(['"', '\'', '.']).trim();
+    (!title.is_empty()).then(|| title.to_string())
+}
 
 impl GptClient {
-    pub fn new() -> Self {
+    pub fn new(persona: Persona, project: Option<Project>) -> Self {
+        let model = persona
+            .model
+            .clone()
+            .or_else(|| project.as_ref()?.model.clone())
+            .unwrap_or_else(default_model);
         GptClient {
             client: reqwest::Client::new(),
-            messages: Vec::new(),
+            session: Session::new(model),
+            persona,
+            project,
+            first_token_timeout: timeout_from_env("RGPT_FIRST_TOKEN_TIMEOUT", 30),
+            idle_timeout: timeout_from_env("RGPT_IDLE_TIMEOUT", 20),
+            max_tokens: env::var("RGPT_MAX_TOKENS")
+                .ok()
+                .and_then(|s| s.parse().ok()),
+            auto_continue: env::var("RGPT_AUTO_CONTINUE").is_ok_and(|s| s == "1" || s == "true"),
+            logprobs: logprobs_from_env(),
+            choices: env::var("RGPT_CHOICES")
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(1),
+            alternatives: Vec::new(),
+            compare: Vec::new(),
+            switch: Switch::Retroactive,
+            title_requested: String::new(),
+        }
+    }
+
+    /// Model that is used for the conversation - the persona or the project may override the default
+    fn model(&self) -> String {
+        self.persona
+            .model
+            .clone()
+            .or_else(|| self.project.as_ref()?.model.clone())
+            .unwrap_or_else(default_model)
+    }
+
+    /// The system prompt with the facts about the machine filled in,
+    /// together with the instructions and files of the project (which are used as they are)
+    fn system(&self, prompt: &str) -> Msg {
+        let prompt = facts::expand(prompt);
+        let project = self
+            .project
+            .as_ref()
+            .map(Project::context)
+            .unwrap_or_default();
+        if project.is_empty() {
+            Msg::new("system", prompt)
+        } else {
+            Msg::new("system", format!("{prompt}\n\n{project}"))
         }
     }
 
+    /// Prepares a request to the chat completions endpoint
+    fn request(&self, rq: &GptReq) -> Result<RequestBuilder> {
+        chat_request(&self.client, rq)
+    }
+
+    /// Sends a request without streaming.
+    ///
+    /// There are no chunks to watch, so the whole answer has to arrive within a fixed time -
+    /// the first token timeout, and some rounds of the idle timeout for the answer itself.
+    async fn send(&self, rq: &GptReq) -> Result<GptRes> {
+        let limit = self.first_token_timeout + 3 * self.idle_timeout;
+        let request = async {
+            let response = self.request(rq)?.send().await?.error_for_status()?;
+            Ok(response.json().await?)
+        };
+        timeout(limit, request)
+            .await
+            .map_err(|_| Error::FirstToken(limit))?
+    }
+
+    /// Generates a title for the session in the background, once the first question is answered.
+    ///
+    /// The title is sent back via `title_tx`, together with the id of the session.
+    fn request_title(&mut self, title_tx: &mpsc::Sender<(String, String)>) {
+        if self.session.title.is_some()
+            || self.title_requested == self.session.id
+            || !self.session.messages.iter().any(|m| m.role == "assistant")
+        {
+            return;
+        }
+        self.title_requested = self.session.id.clone();
+        let client = self.client.clone();
+        let title_tx = title_tx.clone();
+        let id = self.session.id.clone();
+        // If we can't reach the api, we just use the first question
+        let fallback = self.session.title();
+        let conversation: Vec<ReqMsg> = self
+            .session
+            .messages
+            .iter()
+            .filter(|m| m.role != "system")
+            .take(2)
+            .map(|m| ReqMsg {
+                role: m.role.clone(),
+                content: m.content.chars().take(2_000).collect(),
+            })
+            .collect();
+        tokio::spawn(async move {
+            let title = generate_title(&client, conversation)
+                .await
+                .unwrap_or(fallback);
+            // The client may be gone already, which is fine
+            let _ = title_tx.send((id, title)).await;
+        });
+    }
+
+    /// Requests a complete (non-streamed) answer to the current conversation from `model`
+    async fn complete(&self, model: &str) -> Result<GptRes> {
+        let rq = GptReq {
+            model: model.to_string(),
+            messages: self.session.messages.iter().map(ReqMsg::from).collect(),
+            stream: false,
+            stream_options: None,
+            max_tokens: self.max_tokens,
+            logprobs: None,
+            top_logprobs: None,
+            n: None,
+            temperature: self.persona.temperature,
+        };
+        self.send(&rq).await
+    }
+
+    /// Asks a single question outside of the conversation (nothing is stored)
+    pub async fn ask(&self, question: &str) -> Result<String> {
+        let messages = [
+            self.system(&self.persona.prompt),
+            Msg::new("user", question.to_string()),
+        ];
+        let rq = GptReq {
+            model: self.model(),
+            messages: messages.iter().map(ReqMsg::from).collect(),
+            stream: false,
+            stream_options: None,
+            max_tokens: self.max_tokens,
+            logprobs: None,
+            top_logprobs: None,
+            n: None,
+            temperature: self.persona.temperature,
+        };
+        let res = self.send(&rq).await?;
+        let answer = res.choices.into_iter().next().map(|c| c.message.content);
+        Ok(answer.unwrap_or_default())
+    }
+
+    /// Sends the current conversation to all models in `self.compare` at once.
+    ///
+    /// The first successful answer is kept in the conversation.
+    async fn compare_answers(&mut self, output_tx: &mpsc::Sender<Output>) -> Result<()> {
+        let this = &*self;
+        let requests = this.compare.iter().map(|model| async move {
+            let start = Instant::now();
+            let result = this.complete(model).await;
+            (model.clone(), result, start.elapsed())
+        });
+        let comparisons: Vec<Comparison> = join_all(requests)
+            .await
+            .into_iter()
+            .map(|(model, result, latency)| match result {
+                Ok(res) => Comparison {
+                    model,
+                    answer: res
+                        .choices
+                        .into_iter()
+                        .next()
+                        .map(|c| c.message.content)
+                        .ok_or_else(|| "No answer".to_string()),
+                    latency,
+                    usage: Some(res.usage),
+                },
+                Err(e) => Comparison {
+                    model,
+                    answer: Err(e.to_string()),
+                    latency,
+                    usage: None,
+                },
+            })
+            .collect();
+        for usage in comparisons.iter().flat_map(|c| &c.usage) {
+            self.session.usage.add(usage);
+        }
+        match comparisons.iter().find_map(|c| c.answer.as_ref().ok()) {
+            Some(answer) => self
+                .session
+                .messages
+                .push(Msg::new("assistant", answer.clone())),
+            None => {
+                self.session.messages.pop();
+            }
+        }
+        output_tx.send(Output::Comparison(comparisons)).await?;
+        self.save(output_tx).await?;
+        output_tx.send(Output::End).await?;
+        Ok(())
+    }
+
+    /// Sends the conversation to the api and streams the answers into `answers`.
+    ///
+    /// Every received word is also forwarded to `output_tx`.
+    /// If the api does not respond in time, the request is aborted with an error,
+    /// but everything received so far stays in `answers`.
+    /// If `continuation` is set, the model is asked to continue its last answer.
+    ///
+    /// Returns the reason why the model stopped the first answer, if it told us.
+    async fn stream_answer(
+        &mut self,
+        output_tx: &mpsc::Sender<Output>,
+        answers: &mut Vec<String>,
+        continuation: bool,
+    ) -> Result<Option<String>> {
+        let mut messages: Vec<ReqMsg> = self.session.messages.iter().map(ReqMsg::from).collect();
+        if continuation {
+            messages.push(ReqMsg::from(&Msg::new("user", CONTINUE.to_string())));
+        }
+        let rq = GptReq {
+            model: self.model(),
+            messages,
+            stream: true,
+            stream_options: Some(StreamOptions {
+                include_usage: true,
+            }),
+            max_tokens: self.max_tokens,
+            logprobs: self.logprobs.then_some(true),
+            top_logprobs: self.logprobs.then_some(TOP_LOGPROBS),
+            // A continuation only makes sense for the answer we kept
+            n: (self.choices > 1 && !continuation).then_some(self.choices),
+            temperature: self.persona.temperature,
+        };
+
+        // The time-to-first-token includes establishing the connection
+        let first_token_deadline = Instant::now() + self.first_token_timeout;
+        let request = self.request(&rq)?.send();
+        let mut response_stream = timeout_at(first_token_deadline, request)
+            .await
+            .map_err(|_| Error::FirstToken(self.first_token_timeout))??
+            .error_for_status()?
+            .bytes_stream()
+            .eventsource();
+
+        let mut finish_reason = None;
+        loop {
+            let item = if answers.iter().all(String::is_empty) {
+                timeout_at(first_token_deadline, response_stream.next())
+                    .await
+                    .map_err(|_| Error::FirstToken(self.first_token_timeout))?
+            } else {
+                timeout(self.idle_timeout, response_stream.next())
+                    .await
+                    .map_err(|_| Error::Idle(self.idle_timeout))?
+            };
+            let Some(item) = item else {
+                break;
+            };
+            let event = item?;
+            let parsed: Chunk = match serde_json::from_str(&event.data) {
+                Ok(value) => value,
+                Err(e) => {
+                    if event.data != "[DONE]" {
+                        eprintln!("{} could not be parsed: {e}", event.data);
+                    }
+                    continue;
+                }
+            };
+            if let Some(usage) = &parsed.usage {
+                self.session.usage.add(usage);
+            }
+            for choice in parsed.choices {
+                if choice.index >= answers.len() {
+                    answers.resize(choice.index + 1, String::new());
+                }
+                // Only the first answer is streamed live, the alternatives are shown afterwards
+                if choice.index > 0 {
+                    if let Some(word) = choice.delta.content {
+                        answers[choice.index].push_str(&word);
+                        output_tx
+                            .send(Output::Alternative(choice.index, word))
+                            .await?;
+                    }
+                    continue;
+                }
+                if let Some(word) = choice.delta.content {
+                    answers[0].push_str(&word);
+                    output_tx.send(Output::Data(word)).await?;
+                }
+                if let Some(tokens) = choice.logprobs.and_then(|l| l.content) {
+                    output_tx.send(Output::Tokens(tokens)).await?;
+                }
+                if choice.finish_reason.is_some() {
+                    finish_reason = choice.finish_reason;
+                }
+            }
+        }
+        Ok(finish_reason)
+    }
+
+    /// Streams the answer to the current conversation and remembers it.
+    ///
+    /// Answers that were cut off because of the token limit are either continued
+    /// automatically, or the user is told how to continue them.
+    async fn respond(
+        &mut self,
+        output_tx: &mpsc::Sender<Output>,
+        mut continuation: bool,
+    ) -> Result<()> {
+        let mut rounds = 0;
+        self.alternatives.clear();
+        loop {
+            let mut answers = vec![String::with_capacity(1_000)];
+            let finish_reason = match self
+                .stream_answer(output_tx, &mut answers, continuation)
+                .await
+            {
+                Ok(reason) => reason,
+                Err(e @ Error::Send(_)) => return Err(e),
+                Err(e) => {
+                    output_tx.send(Output::Error(e.to_string())).await?;
+                    None
+                }
+            };
+            let answer = answers[0].clone();
+            if answers.len() > 1 {
+                self.alternatives = answers;
+            }
+            if continuation {
+                if let Some(last) = self.session.messages.last_mut() {
+                    last.content.push_str(&answer);
+                    // The first alternative is the answer we kept, including its continuation
+                    if let Some(first) = self.alternatives.first_mut() {
+                        first.clone_from(&last.content);
+                    }
+                }
+            } else if answer.is_empty() {
+                // Nothing came back, so forget the question as well;
+                // otherwise the next question would be stacked on top of it.
+                self.session.messages.pop();
+                self.alternatives.clear();
+                break;
+            } else {
+                // Remember the answer as whole (even if it is only partial)
+                // and append it to the conversation
+                self.session
+                    .messages
+                    .push(Msg::new("assistant", answer.clone()));
+            }
+            match finish_reason.as_deref() {
+                Some("length")
+                    if self.auto_continue && rounds < MAX_AUTO_CONTINUE && !answer.is_empty() =>
+                {
+                    rounds += 1;
+                    continuation = true;
+                    continue;
+                }
+                Some("length") => {
+                    output_tx
+                        .send(Output::Info(
+                            "The answer was cut off by the token limit - use '/continue' to get the rest".to_string(),
+                        ))
+                        .await?;
+                }
+                Some("content_filter") => {
+                    output_tx
+                        .send(Output::Info(
+                            "The answer was stopped by OpenAI's content filter".to_string(),
+                        ))
+                        .await?;
+                }
+                _ => (),
+            }
+            break;
+        }
+        self.save(output_tx).await?;
+        // Let the outside world know, that chatgpt is done now
+        output_tx.send(Output::End).await?;
+        Ok(())
+    }
+
+    /// Puts the pinned ones of the `removed` messages back, right after the system prompt
+    fn restore_pins(&mut self, removed: Vec<Msg>) {
+        let start = self
+            .session
+            .messages
+            .iter()
+            .take_while(|m| m.role == "system" || m.pinned)
+            .count();
+        // The conversation already has its own system prompt
+        let pins = removed
+            .into_iter()
+            .filter(|m| m.pinned && m.role != "system");
+        self.session.messages.splice(start..start, pins);
+    }
+
+    /// Rolls the conversation back to its first `n` messages, keeping the pinned ones
+    fn truncate(&mut self, n: usize, context: &Msg) {
+        let removed = self
+            .session
+            .messages
+            .split_off(n.min(self.session.messages.len()));
+        self.restore_pins(removed);
+        // A prompt that was switched to after message `n` still applies
+        if !self.session.messages.iter().any(|m| m.role == "system") {
+            self.session.messages.push(context.clone());
+        }
+        self.alternatives.clear();
+    }
+
+    /// Starts a new session with the current context and the pinned messages of the old one
+    fn clear(&mut self, context: &Msg) {
+        // The old conversation stays on disk, the new one gets its own session
+        let new = Session::new(self.model());
+        let old = std::mem::replace(&mut self.session, new);
+        self.session.messages.push(context.clone());
+        self.restore_pins(old.messages);
+    }
+
+    /// Replaces the system prompt - there is only ever a single one in the conversation
+    fn set_system(&mut self, context: &Msg) {
+        self.session.messages.retain(|m| m.role != "system");
+        match self.switch {
+            Switch::Retroactive => self.session.messages.insert(0, context.clone()),
+            Switch::FromNowOn => self.session.messages.push(context.clone()),
+        }
+    }
+
+    /// Writes the current session to disk (if the user asked anything yet)
+    async fn save(&mut self, output_tx: &mpsc::Sender<Output>) -> Result<()> {
+        let stored = self.session.path().is_ok_and(|path| path.exists());
+        if !stored && !self.session.messages.iter().any(|m| m.role == "user") {
+            return Ok(());
+        }
+        self.session.model = self.model();
+        if let Err(e) = self.session.save() {
+            output_tx
+                .send(Output::Error(Error::from(e).to_string()))
+                .await?;
+        }
+        Ok(())
+    }
+
     pub async fn event_stream(
         mut self,
         mut input_rx: mpsc::Receiver<Input>,
         output_tx: mpsc::Sender<Output>,
     ) -> Result<()> {
         // Base context
-        let mut context = Msg {
-            role: "system".to_string(),
-            content: PROGRAMMING.to_string(),
-        };
-        self.messages.push(context.clone());
-        while let Some(input) = input_rx.recv().await {
+        let mut context = self.system(&self.persona.prompt);
+        self.session.messages.push(context.clone());
+        let (title_tx, mut title_rx) = mpsc::channel(1);
+        loop {
+            let input = tokio::select! {
+                input = input_rx.recv() => match input {
+                    Some(input) => input,
+                    None => break,
+                },
+                Some((id, title)) = title_rx.recv() => {
+                    // The user may have moved on to another session in the meantime
+                    if id == self.session.id {
+                        self.session.title = Some(title);
+                        self.session.set_terminal_title();
+                        if let Err(e) = self.session.write() {
+                            output_tx.send(Output::Error(Error::from(e).to_string())).await?;
+                        }
+                    }
+                    continue;
+                }
+            };
             match input {
                 Input::Text(input) => {
-                    self.messages.push(Msg {
-                        role: "user".to_string(),
-                        content: input,
-                    });
-
-                    let model = env::var("OPENAI_MODEL").unwrap_or_else(|_| "gpt-4o".to_string());
-
-                    let rq = GptReq {
-                        model,
-                        messages: self.messages.clone(),
-                        stream: true,
-                    };
-
-                    let openai_key = env::var("OPENAI_KEY")?;
-
-                    let mut response_stream = self
-                        .client
-                        .post("https://api.openai.com/v1/chat/completions")
-                        .bearer_auth(openai_key)
-                        .json(&rq)
-                        .send()
-                        .await?
-                        .error_for_status()?
-                        .bytes_stream()
-                        .eventsource();
-
-                    let mut answer = String::with_capacity(1_000);
-                    while let Some(item) = response_stream.next().await {
-                        let event = item.unwrap();
-                        let parsed: Chunk = match serde_json::from_str(&event.data) {
-                            Ok(value) => value,
-                            Err(e) => {
-                                if event.data != "[DONE]" {
-                                    eprintln!("{} could not be parsed: {e}", event.data);
-                                }
-                                continue;
-                            }
-                        };
-                        for word in parsed.choices.into_iter().flat_map(|c| c.delta.content) {
-                            answer.push_str(&word);
-                            output_tx.send(Output::Data(word)).await?;
-                        }
+                    self.session.messages.push(Msg::new("user", input));
+
+                    if self.compare.is_empty() {
+                        self.respond(&output_tx, false).await?;
+                    } else {
+                        self.alternatives.clear();
+                        self.compare_answers(&output_tx).await?;
                     }
-                    // Let the outside world know, that chatgpt is done now
-                    output_tx.send(Output::End).await?;
-                    // Remember the answer as whole and append it to the conversation
-                    self.messages.push(Msg {
-                        role: "assistant".to_string(),
-                        content: answer,
-                    });
-                }
-                Input::Context(new_context) => {
-                    match new_context {
-                        UseContext::Basic => {
-                            context = Msg {
-                                role: "system".to_string(),
-                                content: BASIC_CONTEXT.to_string(),
-                            }
-                        }
-                        UseContext::Short => {
-                            context = Msg {
-                                role: "system".to_string(),
-                                content: NO_REPEAT.to_string(),
-                            }
-                        }
-                        UseContext::Programming => {
-                            context = Msg {
-                                role: "system".to_string(),
-                                content: PROGRAMMING.to_string(),
-                            }
+                    self.request_title(&title_tx);
+                }
+                Input::Compare(models) => self.compare = models,
+                Input::Snapshot(tx) => {
+                    // Nobody cares, if the receiver is already gone
+                    let _ = tx.send(self.session.clone());
+                }
+                Input::Truncate(n) => {
+                    self.truncate(n, &context);
+                    self.save(&output_tx).await?;
+                }
+                Input::Fork(tx) => {
+                    // The original session stays on disk as it is
+                    self.session = self.session.fork();
+                    self.session.set_terminal_title();
+                    self.alternatives.clear();
+                    self.save(&output_tx).await?;
+                    let _ = tx.send(self.session.clone());
+                }
+                Input::Pin(index, pinned) => {
+                    if let Some(msg) = self.session.messages.get_mut(index) {
+                        msg.pinned = pinned;
+                        self.save(&output_tx).await?;
+                    }
+                }
+                Input::Load(mut session) => {
+                    match session.messages.iter().rposition(|m| m.role == "system") {
+                        // Older sessions may contain several stacked prompts - only the last one counts
+                        Some(last) => {
+                            let stacked = session.messages[..last]
+                                .iter()
+                                .filter(|m| m.role == "system")
+                                .count();
+                            context = session.messages.remove(last);
+                            session.messages.retain(|m| m.role != "system");
+                            session.messages.insert(last - stacked, context.clone());
                         }
+                        // e.g. imported conversations
+                        None => session.messages.insert(0, context.clone()),
+                    }
+                    self.session = *session;
+                    self.session.set_terminal_title();
+                    self.alternatives.clear();
+                }
+                Input::Logprobs(enabled) => self.logprobs = enabled,
+                Input::Choices(n) => self.choices = n.max(1),
+                Input::Pick(index) => {
+                    let last = self
+                        .session
+                        .messages
+                        .last_mut()
+                        .filter(|m| m.role == "assistant");
+                    if let (Some(last), Some(answer)) = (last, self.alternatives.get(index)) {
+                        last.content = answer.clone();
+                        // The session was saved with the first answer already
+                        self.save(&output_tx).await?;
+                    }
+                    self.alternatives.clear();
+                }
+                Input::Continue => {
+                    if self
+                        .session
+                        .messages
+                        .last()
+                        .is_some_and(|m| m.role == "assistant")
+                    {
+                        self.respond(&output_tx, true).await?;
+                    } else {
+                        output_tx
+                            .send(Output::Error("There is no answer to continue".to_string()))
+                            .await?;
+                        output_tx.send(Output::End).await?;
                     }
-                    self.messages.push(context.clone());
                 }
+                Input::Persona(persona) => {
+                    context = self.system(&persona.prompt);
+                    self.persona = persona;
+                    self.set_system(&context);
+                }
+                Input::System(prompt) => {
+                    context = self.system(&prompt);
+                    self.set_system(&context);
+                }
+                Input::Switch(switch) => self.switch = switch,
                 Input::Clear => {
                     println!("--- System: Clearing conversation");
-                    self.messages.clear();
-                    // Use last context
-                    self.messages.push(context.clone());
+                    self.clear(&context);
                 }
             }
         }
         Ok(())
     }
 }
+
+#[cfg(test)]
+fn test_client(messages: &[(&str, &str, bool)]) -> GptClient {
+    let mut client = GptClient::new(crate::persona::builtins().remove(0), None);
+    client.session.messages = messages
+        .iter()
+        .map(|&(role, content, pinned)| Msg {
+            pinned,
+            ..Msg::new(role, content.to_string())
+        })
+        .collect();
+    client
+}
+
+#[cfg(test)]
+fn contents(client: &GptClient) -> Vec<&str> {
+    client
+        .session
+        .messages
+        .iter()
+        .map(|m| m.content.as_str())
+        .collect()
+}
+
+#[test]
+fn test_truncate() {
+    let context = Msg::new("system", "context".to_string());
+    let conversation = [
+        ("system", "context", false),
+        ("user", "q1", false),
+        ("assistant", "a1", true),
+        ("user", "q2", false),
+        ("assistant", "a2", false),
+    ];
+
+    // `/undo` removes the last question and its answer
+    let mut client = test_client(&conversation);
+    client.truncate(3, &context);
+    assert_eq!(contents(&client), ["context", "q1", "a1"]);
+
+    // `/rewind 1` keeps the pinned answer right after the system prompt
+    let mut client = test_client(&conversation);
+    client.truncate(1, &context);
+    assert_eq!(contents(&client), ["context", "a1"]);
+
+    // A prompt that was switched to later on still applies
+    let mut client = test_client(&[
+        ("user", "q1", false),
+        ("assistant", "a1", false),
+        ("system", "later", false),
+    ]);
+    client.truncate(1, &context);
+    assert_eq!(contents(&client), ["q1", "context"]);
+}
+
+#[test]
+fn test_clear_keeps_pins() {
+    let context = Msg::new("system", "new context".to_string());
+    let mut client = test_client(&[
+        ("system", "old context", true),
+        ("user", "api spec", true),
+        ("assistant", "a1", false),
+    ]);
+    client.clear(&context);
+    assert_eq!(contents(&client), ["new context", "api spec"]);
+}
+
+#[test]
+fn test_set_system() {
+    let conversation = [
+        ("system", "old", false),
+        ("user", "q1", false),
+        ("assistant", "a1", false),
+    ];
+    let context = Msg::new("system", "new".to_string());
+    let mut client = test_client(&conversation);
+    client.set_system(&context);
+    assert_eq!(contents(&client), ["new", "q1", "a1"]);
+
+    let mut client = test_client(&conversation);
+    client.switch = Switch::FromNowOn;
+    client.set_system(&context);
+    assert_eq!(contents(&client), ["q1", "a1", "new"]);
+}
+
+#[test]
+fn test_system_expands_only_the_prompt() {
+    let project = Project {
+        root: std::path::PathBuf::new(),
+        persona: None,
+        model: None,
+        instructions: Some("Placeholders look like {{cwd}}".to_string()),
+        include: Vec::new(),
+    };
+    let client = GptClient::new(crate::persona::builtins().remove(0), Some(project));
+    let cwd = env::current_dir().unwrap().display().to_string();
+    assert_eq!(
+        client.system("Working in {{cwd}}").content,
+        format!("Working in {cwd}\n\nPlaceholders look like {{{{cwd}}}}")
+    );
+}
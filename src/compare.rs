@@ -0,0 +1,132 @@
+use std::error::Error;
+
+use pulldown_cmark_mdcat::TerminalSize;
+
+use crate::client::Comparison;
+use crate::MdPrinter;
+
+/// Separator between two columns
+const SEPARATOR: &str = " │ ";
+
+/// Parses a comma separated list of models, like "gpt-4o,gpt-4o-mini".
+///
+/// "off" (or nothing at all) disables the comparison.
+pub fn parse_models(input: &str) -> Vec<String> {
+    if input.trim() == "off" {
+        return Vec::new();
+    }
+    input
+        .split(',')
+        .map(str::trim)
+        .filter(|model| !model.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/// Number of characters that are actually visible in the terminal
+/// (skips all ansi escape sequences).
+fn visible_width(line: &str) -> usize {
+    let mut width = 0;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '\x1b' {
+            width += 1;
+            continue;
+        }
+        match chars.next() {
+            // CSI - ends with a character in the range '@'..='~'
+            Some('[') => {
+                for c in chars.by_ref() {
+                    if ('@'..='~').contains(&c) {
+                        break;
+                    }
+                }
+            }
+            // OSC - ends with BEL or ESC '\'
+            Some(']') => {
+                while let Some(c) = chars.next() {
+                    if c == '\x07' {
+                        break;
+                    }
+                    if c == '\x1b' && chars.peek() == Some(&'\\') {
+                        chars.next();
+                        break;
+                    }
+                }
+            }
+            _ => (),
+        }
+    }
+    width
+}
+
+/// Renders a single answer (with a header for the model) into lines
+fn render(
+    md: &MdPrinter,
+    comparison: &Comparison,
+    columns: u16,
+) -> Result<Vec<String>, Box<dyn Error>> {
+    let stats = match &comparison.usage {
+        Some(usage) => format!(
+            "{:.2}s, {} prompt + {} completion tokens",
+            comparison.latency.as_secs_f64(),
+            usage.prompt_tokens,
+            usage.completion_tokens
+        ),
+        None => format!("{:.2}s", comparison.latency.as_secs_f64()),
+    };
+    let answer = match &comparison.answer {
+        Ok(answer) => answer.clone(),
+        Err(e) => format!("**Error:** {e}"),
+    };
+    let markdown = format!("# {}\n\n*{stats}*\n\n{answer}", comparison.model);
+    let mut buffer = Vec::new();
+    md.write(&mut buffer, markdown, columns)?;
+    Ok(String::from_utf8_lossy(&buffer)
+        .lines()
+        .map(str::to_string)
+        .collect())
+}
+
+/// Prints the answers of all models next to each other
+pub fn print_columns(md: &MdPrinter, comparisons: &[Comparison]) -> Result<(), Box<dyn Error>> {
+    if comparisons.is_empty() {
+        return Ok(());
+    }
+    let total = TerminalSize::detect().unwrap_or_default().columns as usize;
+    let separators = SEPARATOR.chars().count() * (comparisons.len() - 1);
+    let width = (total.saturating_sub(separators) / comparisons.len()).max(10);
+
+    let columns = comparisons
+        .iter()
+        .map(|c| render(md, c, width as u16))
+        .collect::<Result<Vec<_>, _>>()?;
+    let height = columns.iter().map(Vec::len).max().unwrap_or(0);
+
+    for row in 0..height {
+        let mut line = String::new();
+        for (i, column) in columns.iter().enumerate() {
+            let cell = column.get(row).map(String::as_str).unwrap_or("");
+            line.push_str(cell);
+            // Make sure no style leaks into the next column
+            line.push_str("\x1b[0m");
+            if i + 1 < columns.len() {
+                let padding = width.saturating_sub(visible_width(cell));
+                line.push_str(&" ".repeat(padding));
+                line.push_str(SEPARATOR);
+            }
+        }
+        println!("{line}");
+    }
+    Ok(())
+}
+
+#[test]
+fn test_visible_width() {
+    assert_eq!(visible_width("plain"), 5);
+    assert_eq!(visible_width("\x1b[1;31mbold\x1b[0m"), 4);
+    assert_eq!(
+        visible_width("\x1b]8;;https://x.y\x1b\\link\x1b]8;;\x1b\\"),
+        4
+    );
+}
@@ -0,0 +1,179 @@
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+
+use crate::persona::{self, Persona};
+use crate::template::fence;
+
+/// Directory of all user configuration (`$XDG_CONFIG_HOME/rgpt`, or `~/.config/rgpt`)
+pub fn config_dir() -> io::Result<PathBuf> {
+    let config_dir = match env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => {
+            let home = env::var_os("HOME")
+                .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "HOME is not set"))?;
+            PathBuf::from(home).join(".config")
+        }
+    };
+    Ok(config_dir.join("rgpt"))
+}
+
+/// Settings from `~/.config/rgpt/config.toml`
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// Name of the persona that is used at startup
+    pub context: Option<String>,
+}
+
+impl Config {
+    /// Loads the config file - a missing file is the same as an empty one
+    pub fn load() -> Result<Config, String> {
+        let path = config_dir().map_err(|e| e.to_string())?.join("config.toml");
+        match fs::read_to_string(&path) {
+            Ok(content) => toml::from_str(&content)
+                .map_err(|e| format!("Invalid config file {}: {e}", path.display())),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Config::default()),
+            Err(e) => Err(format!("Failed to read {}: {e}", path.display())),
+        }
+    }
+
+    /// The persona to start with.
+    ///
+    /// The environment variable RGPT_CONTEXT takes precedence over the project,
+    /// which takes precedence over the config file - without any of them we use the 'programming' persona.
+    pub fn default_persona(&self, project: Option<&Project>) -> Result<Persona, String> {
+        let project_persona = project.and_then(|p| p.persona.clone());
+        let (name, source) = match env::var("RGPT_CONTEXT") {
+            Ok(name) if !name.trim().is_empty() => (name, "RGPT_CONTEXT"),
+            _ => match (project_persona, &self.context) {
+                (Some(name), _) => (name, PROJECT_FILE),
+                (None, Some(name)) => (name.clone(), "config.toml"),
+                (None, None) => ("programming".to_string(), "default"),
+            },
+        };
+        let name = name.trim();
+        match persona::find(name) {
+            Some(persona) => Ok(persona),
+            None => {
+                let personas = persona::load_all();
+                let available: Vec<&str> = personas.iter().map(|p| p.name.as_str()).collect();
+                Err(format!(
+                    "Unknown context '{name}' (from {source}) - available are: {}",
+                    available.join(", ")
+                ))
+            }
+        }
+    }
+}
+
+/// Name of the project configuration, that is searched in the current directory and its parents
+const PROJECT_FILE: &str = ".rgpt.toml";
+
+/// Settings that are shared by everyone working on a repository
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Project {
+    /// Directory that contains the `.rgpt.toml`
+    #[serde(skip)]
+    pub root: PathBuf,
+    /// Persona that is used at startup
+    pub persona: Option<String>,
+    /// Overrides OPENAI_MODEL (but not the model of a persona)
+    pub model: Option<String>,
+    /// Added to every system prompt
+    pub instructions: Option<String>,
+    /// Files (relative to the root) whose content is added to every system prompt
+    #[serde(default)]
+    pub include: Vec<PathBuf>,
+}
+
+impl Project {
+    /// Finds the `.rgpt.toml` of the project we are in, walking up from `dir`
+    pub fn discover(dir: &Path) -> Result<Option<Project>, String> {
+        for dir in dir.ancestors() {
+            let path = dir.join(PROJECT_FILE);
+            let content = match fs::read_to_string(&path) {
+                Ok(content) => content,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(format!("Failed to read {}: {e}", path.display())),
+            };
+            let mut project: Project = toml::from_str(&content)
+                .map_err(|e| format!("Invalid project config {}: {e}", path.display()))?;
+            project.root = dir.to_path_buf();
+            return Ok(Some(project));
+        }
+        Ok(None)
+    }
+
+    /// Resolves an included file - it has to be inside the project,
+    /// because a cloned repository must not be able to send e.g. `~/.ssh/id_rsa` along
+    fn include_path(&self, file: &Path) -> io::Result<PathBuf> {
+        let root = self.root.canonicalize()?;
+        let path = root.join(file).canonicalize()?;
+        if !path.starts_with(&root) {
+            return Err(io::Error::other("the file is outside of the project"));
+        }
+        Ok(path)
+    }
+
+    /// The part of the system prompt that comes from the project - instructions and included files
+    pub fn context(&self) -> String {
+        let mut context = String::new();
+        if let Some(instructions) = &self.instructions {
+            context.push_str(instructions.trim());
+        }
+        for file in self.include.iter() {
+            match self.include_path(file).and_then(fs::read_to_string) {
+                Ok(content) => context.push_str(&format!(
+                    "\n\nContent of {}:\n\n{}",
+                    file.display(),
+                    fence(file, &content)
+                )),
+                Err(e) => eprintln!("--- System: Skipping {}: {e}", file.display()),
+            }
+        }
+        context.trim().to_string()
+    }
+}
+
+#[test]
+fn test_discover_project() {
+    let root = env::temp_dir().join(format!("rgpt-project-{}", std::process::id()));
+    let nested = root.join("src").join("bin");
+    fs::create_dir_all(&nested).unwrap();
+    fs::write(
+        root.join(PROJECT_FILE),
+        "persona = \"reviewer\"\ninstructions = \"We use tokio.\"\ninclude = [\"notes.md\"]\n",
+    )
+    .unwrap();
+    fs::write(root.join("notes.md"), "MSRV 1.75\n").unwrap();
+    let secret = env::temp_dir().join(format!("rgpt-secret-{}", std::process::id()));
+    fs::write(&secret, "SECRET\n").unwrap();
+
+    let project = Project::discover(&nested).unwrap().unwrap();
+    assert_eq!(project.root, root);
+    assert_eq!(project.persona.as_deref(), Some("reviewer"));
+    assert_eq!(
+        project.context(),
+        "We use tokio.\n\nContent of notes.md:\n\n```md\nMSRV 1.75\n```"
+    );
+
+    // Files outside of the project are never included
+    let outside = Project {
+        root: root.clone(),
+        include: vec![
+            secret.clone(),
+            Path::new("..").join(secret.file_name().unwrap()),
+        ],
+        ..Project::default()
+    };
+    assert!(outside.include_path(&outside.include[0]).is_err());
+    assert!(outside.include_path(&outside.include[1]).is_err());
+    assert_eq!(outside.context(), "");
+    fs::remove_dir_all(&root).unwrap();
+    fs::remove_file(&secret).unwrap();
+}
@@ -0,0 +1,200 @@
+//! Optional encryption of the stored sessions.
+//!
+//! If a key file (environment variable RGPT_KEY_FILE) or a passphrase (RGPT_PASSPHRASE)
+//! is configured, every session is encrypted with XChaCha20-Poly1305 before it is written.
+//! The key is derived with argon2 from the passphrase and a random salt,
+//! that is stored once next to the sessions - together with a value encrypted with the key,
+//! so a wrong passphrase is refused instead of mixing sessions with different keys.
+//! Encrypted files are recognized by their header, so plaintext and encrypted sessions
+//! can live side by side and are decrypted transparently.
+
+use std::env;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::OnceLock;
+
+use argon2::Argon2;
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng};
+use chacha20poly1305::{Key, XChaCha20Poly1305, XNonce};
+
+use crate::session::sessions_dir;
+
+/// Every encrypted file starts with this
+const MAGIC: &[u8] = b"RGPTENC1";
+const NONCE_LEN: usize = 24;
+const SALT_LEN: usize = 16;
+
+/// Encrypted with the key and stored next to the salt, to detect a wrong passphrase
+const KEY_CHECK: &[u8] = b"rgpt key check";
+
+/// Reads the passphrase from the key file or the environment (if any is configured)
+fn passphrase() -> io::Result<Option<Vec<u8>>> {
+    if let Some(path) = env::var_os("RGPT_KEY_FILE") {
+        let content = fs::read(&path).map_err(|e| {
+            io::Error::other(format!(
+                "Failed to read key file {}: {e}",
+                path.to_string_lossy()
+            ))
+        })?;
+        let content = content.trim_ascii_end().to_vec();
+        if content.is_empty() {
+            return Err(io::Error::other("The key file is empty"));
+        }
+        return Ok(Some(content));
+    }
+    Ok(env::var("RGPT_PASSPHRASE")
+        .ok()
+        .filter(|p| !p.is_empty())
+        .map(String::into_bytes))
+}
+
+/// The salt of the session store, which is created on first use
+fn salt() -> io::Result<Vec<u8>> {
+    let path = sessions_dir()?.join(".salt");
+    match fs::read(&path) {
+        Ok(salt) => Ok(salt),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            let mut salt = vec![0; SALT_LEN];
+            OsRng.fill_bytes(&mut salt);
+            fs::create_dir_all(sessions_dir()?)?;
+            fs::write(&path, &salt)?;
+            Ok(salt)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+fn encrypt(key: &Key, plaintext: &[u8]) -> io::Result<Vec<u8>> {
+    let cipher = XChaCha20Poly1305::new(key);
+    let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+    let ciphertext = cipher
+        .encrypt(&nonce, plaintext)
+        .map_err(|_| io::Error::other("Failed to encrypt session"))?;
+    let mut sealed = Vec::with_capacity(MAGIC.len() + NONCE_LEN + ciphertext.len());
+    sealed.extend_from_slice(MAGIC);
+    sealed.extend_from_slice(&nonce);
+    sealed.extend_from_slice(&ciphertext);
+    Ok(sealed)
+}
+
+/// Decrypts `sealed`, which has to start with the header
+fn decrypt(key: &Key, sealed: &[u8]) -> io::Result<Vec<u8>> {
+    let rest = sealed
+        .strip_prefix(MAGIC)
+        .ok_or_else(|| io::Error::other("Session is not encrypted"))?;
+    if rest.len() < NONCE_LEN {
+        return Err(io::Error::other("Encrypted session is truncated"));
+    }
+    let (nonce, ciphertext) = rest.split_at(NONCE_LEN);
+    XChaCha20Poly1305::new(key)
+        .decrypt(XNonce::from_slice(nonce), ciphertext)
+        .map_err(|_| io::Error::other("Failed to decrypt session - wrong key or passphrase?"))
+}
+
+/// Makes sure `key` is the one the existing sessions were encrypted with (by the check value at `path`),
+/// so a mistyped passphrase never ends up with sessions under different keys
+fn check_key(key: &Key, path: &Path) -> io::Result<()> {
+    match fs::read(path) {
+        Ok(sealed) => match decrypt(key, &sealed) {
+            Ok(plaintext) if plaintext == KEY_CHECK => Ok(()),
+            _ => Err(io::Error::other(
+                "Wrong key or passphrase - it doesn't match the one the sessions are encrypted with",
+            )),
+        },
+        Err(e) if e.kind() == io::ErrorKind::NotFound => fs::write(path, encrypt(key, KEY_CHECK)?),
+        Err(e) => Err(e),
+    }
+}
+
+fn derive_key() -> io::Result<Option<Key>> {
+    let Some(passphrase) = passphrase()? else {
+        return Ok(None);
+    };
+    let mut key = Key::default();
+    Argon2::default()
+        .hash_password_into(&passphrase, &salt()?, &mut key)
+        .map_err(|e| io::Error::other(format!("Failed to derive key: {e}")))?;
+    check_key(&key, &sessions_dir()?.join(".keycheck"))?;
+    Ok(Some(key))
+}
+
+/// The key for the session store (derived only once, because argon2 is slow on purpose)
+fn key() -> io::Result<Option<&'static Key>> {
+    static KEY: OnceLock<Result<Option<Key>, String>> = OnceLock::new();
+    KEY.get_or_init(|| derive_key().map_err(|e| e.to_string()))
+        .as_ref()
+        .map(Option::as_ref)
+        .map_err(|e| io::Error::other(e.clone()))
+}
+
+/// Whether new sessions are written encrypted
+pub fn enabled() -> bool {
+    passphrase().is_ok_and(|p| p.is_some())
+}
+
+/// Encrypts `plaintext`, if encryption is enabled
+pub fn seal(plaintext: Vec<u8>) -> io::Result<Vec<u8>> {
+    match key()? {
+        Some(key) => encrypt(key, &plaintext),
+        None => Ok(plaintext),
+    }
+}
+
+/// Decrypts `data`, if it is encrypted - plaintext is returned as it is
+pub fn open(data: Vec<u8>) -> io::Result<Vec<u8>> {
+    if !data.starts_with(MAGIC) {
+        return Ok(data);
+    }
+    let Some(key) = key()? else {
+        return Err(io::Error::other(
+            "Session is encrypted - set RGPT_KEY_FILE or RGPT_PASSPHRASE to read it",
+        ));
+    };
+    decrypt(key, &data)
+}
+
+#[cfg(test)]
+fn test_key(byte: u8) -> Key {
+    *Key::from_slice(&[byte; 32])
+}
+
+#[test]
+fn test_encrypt_decrypt() {
+    let key = test_key(1);
+    let sealed = encrypt(&key, b"secret session").unwrap();
+    assert!(sealed.starts_with(MAGIC));
+    assert!(!sealed.windows(6).any(|w| w == b"secret"));
+    assert_eq!(decrypt(&key, &sealed).unwrap(), b"secret session");
+    assert!(decrypt(&test_key(2), &sealed).is_err());
+
+    // Truncated or tampered files are refused
+    assert!(decrypt(&key, &sealed[..MAGIC.len() + NONCE_LEN / 2]).is_err());
+    assert!(decrypt(&key, &sealed[..sealed.len() - 1]).is_err());
+    let mut tampered = sealed.clone();
+    *tampered.last_mut().unwrap() ^= 1;
+    assert!(decrypt(&key, &tampered).is_err());
+    assert!(decrypt(&key, b"secret session").is_err());
+}
+
+#[test]
+fn test_check_key() {
+    let dir = std::env::temp_dir().join(format!("rgpt-test-keycheck-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join(".keycheck");
+    let _ = fs::remove_file(&path);
+
+    // The first key is stored, from then on only that one is accepted
+    check_key(&test_key(1), &path).unwrap();
+    assert!(path.exists());
+    check_key(&test_key(1), &path).unwrap();
+    assert!(check_key(&test_key(2), &path).is_err());
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_open_plaintext() {
+    let plaintext = br#"{"id":"20240101-120000-000"}"#.to_vec();
+    assert_eq!(open(plaintext.clone()).unwrap(), plaintext);
+}
@@ -0,0 +1,121 @@
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+
+use pulldown_cmark::{html, CodeBlockKind, CowStr, Event, Options, Parser, Tag, TagEnd};
+use syntect::highlighting::ThemeSet;
+use syntect::html::highlighted_html_for_string;
+use syntect::parsing::SyntaxSet;
+
+use crate::session::Session;
+
+/// Header for each message - the same we use in the terminal
+fn header(role: &str) -> Option<&'static str> {
+    match role {
+        "user" => Some("Input"),
+        "assistant" => Some("ChatGPT"),
+        _ => None,
+    }
+}
+
+/// The conversation as markdown, with `# Input` and `# ChatGPT` headers
+pub fn to_markdown(session: &Session) -> String {
+    let mut markdown = String::new();
+    for msg in session.messages.iter() {
+        if let Some(header) = header(&msg.role) {
+            markdown.push_str(&format!("# {header}\n\n{}\n\n", msg.content.trim()));
+        }
+    }
+    markdown
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Renders markdown to html, with syntax highlighting for all fenced code blocks
+fn markdown_to_html(markdown: &str, ss: &SyntaxSet, ts: &ThemeSet) -> String {
+    let theme = &ts.themes["InspiredGitHub"];
+    let mut events = Vec::new();
+    let mut code: Option<(String, String)> = None;
+    for event in Parser::new_ext(markdown, Options::all()) {
+        match (event, code.as_mut()) {
+            (Event::Start(Tag::CodeBlock(CodeBlockKind::Fenced(lang))), None) => {
+                code = Some((lang.to_string(), String::new()));
+            }
+            (Event::Text(text), Some((_, content))) => content.push_str(&text),
+            (Event::End(TagEnd::CodeBlock), Some(_)) => {
+                let (lang, content) = code.take().unwrap_or_default();
+                let syntax = ss
+                    .find_syntax_by_token(lang.split(',').next().unwrap_or_default())
+                    .unwrap_or_else(|| ss.find_syntax_plain_text());
+                let highlighted = highlighted_html_for_string(&content, ss, syntax, theme)
+                    .unwrap_or_else(|_| format!("<pre>{}</pre>", escape_html(&content)));
+                events.push(Event::Html(CowStr::from(highlighted)));
+            }
+            // Raw html from the conversation is shown, not interpreted
+            (Event::Html(raw) | Event::InlineHtml(raw), None) => events.push(Event::Text(raw)),
+            (event, _) => events.push(event),
+        }
+    }
+    let mut output = String::new();
+    html::push_html(&mut output, events.into_iter());
+    output
+}
+
+/// The conversation as a self-contained html page
+pub fn to_html(session: &Session) -> String {
+    let ss = SyntaxSet::load_defaults_newlines();
+    let ts = ThemeSet::load_defaults();
+    let title = escape_html(&session.title());
+    let mut body = String::new();
+    for msg in session.messages.iter() {
+        if let Some(header) = header(&msg.role) {
+            body.push_str(&format!(
+                "<section class=\"{}\">\n<h1>{header}</h1>\n{}</section>\n",
+                msg.role,
+                markdown_to_html(&msg.content, &ss, &ts)
+            ));
+        }
+    }
+    format!(
+        r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>{title}</title>
+<style>
+body {{ max-width: 50em; margin: 2em auto; padding: 0 1em; font-family: sans-serif; line-height: 1.5; }}
+section {{ border-left: 4px solid #ccc; padding-left: 1em; margin-bottom: 2em; }}
+section.user {{ border-color: #4a90d9; }}
+section.assistant {{ border-color: #50a14f; }}
+h1 {{ font-size: 1.1em; color: #555; }}
+pre {{ padding: 0.8em; overflow-x: auto; border-radius: 4px; }}
+code {{ font-family: monospace; }}
+</style>
+</head>
+<body>
+<p><em>{model}, {created}</em></p>
+{body}</body>
+</html>
+"#,
+        model = escape_html(&session.model),
+        created = session.created.format("%Y-%m-%d %H:%M UTC"),
+    )
+}
+
+/// Writes the conversation to `path`.
+///
+/// The format is chosen by the extension - `.html`, `.json` or markdown otherwise.
+pub fn write(session: &Session, path: &Path) -> Result<(), Box<dyn Error>> {
+    let content = match path.extension().and_then(|ext| ext.to_str()) {
+        Some("html" | "htm") => to_html(session),
+        Some("json") => serde_json::to_string_pretty(session)?,
+        _ => to_markdown(session),
+    };
+    fs::write(path, content)?;
+    Ok(())
+}
@@ -0,0 +1,100 @@
+//! Facts about the user's machine, that can be used in system prompts.
+//!
+//! Placeholders like `{{os}}` are expanded when a persona or ad-hoc prompt is set,
+//! before the context of the project is appended - so files that document placeholders stay as they are.
+
+use std::env;
+use std::fs;
+use std::process::Command;
+use std::sync::OnceLock;
+
+use chrono::Local;
+
+/// First line of the output of `program args`, if it runs successfully
+fn command_output(program: &str, args: &[&str]) -> Option<String> {
+    let output = Command::new(program).args(args).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let line = stdout.lines().next()?.trim();
+    (!line.is_empty()).then(|| line.to_string())
+}
+
+/// Name of the distribution (on linux), together with the os and architecture
+fn os() -> String {
+    let distro = fs::read_to_string("/etc/os-release")
+        .ok()
+        .and_then(|release| {
+            release.lines().find_map(|line| {
+                let name = line.strip_prefix("PRETTY_NAME=")?;
+                Some(name.trim_matches('"').to_string())
+            })
+        });
+    let os = format!("{} {}", env::consts::OS, env::consts::ARCH);
+    match distro {
+        Some(distro) => format!("{distro} ({os})"),
+        None => os,
+    }
+}
+
+fn shell() -> Option<String> {
+    let shell = env::var("SHELL").or_else(|_| env::var("ComSpec")).ok()?;
+    let name = shell.rsplit(['/', '\\']).next().unwrap_or(&shell);
+    Some(name.to_string())
+}
+
+/// Installed version of a tool - they don't change while we are running, so we only ask once
+fn version(tool: &'static str, cache: &'static OnceLock<Option<String>>) -> Option<String> {
+    cache
+        .get_or_init(|| command_output(tool, &["--version"]))
+        .clone()
+}
+
+/// The value for a single placeholder, or `None` if we don't know the placeholder
+fn fact(name: &str) -> Option<String> {
+    static RUSTC: OnceLock<Option<String>> = OnceLock::new();
+    static CARGO: OnceLock<Option<String>> = OnceLock::new();
+    let value = match name {
+        "date" => Some(Local::now().format("%Y-%m-%d").to_string()),
+        "time" => Some(Local::now().format("%H:%M %:z").to_string()),
+        "os" => Some(os()),
+        "shell" => shell(),
+        "cwd" => env::current_dir().ok().map(|dir| dir.display().to_string()),
+        "git_branch" => command_output("git", &["rev-parse", "--abbrev-ref", "HEAD"]),
+        "rustc" => version("rustc", &RUSTC),
+        "cargo" => version("cargo", &CARGO),
+        _ => return None,
+    };
+    Some(value.unwrap_or_else(|| "unknown".to_string()))
+}
+
+/// Replaces all known placeholders in `prompt` - unknown ones are kept as they are
+pub fn expand(prompt: &str) -> String {
+    let mut output = String::with_capacity(prompt.len());
+    let mut rest = prompt;
+    while let Some(start) = rest.find("{{") {
+        let Some(end) = rest[start..].find("}}") else {
+            break;
+        };
+        let placeholder = &rest[start..start + end + 2];
+        output.push_str(&rest[..start]);
+        match fact(placeholder[2..placeholder.len() - 2].trim()) {
+            Some(value) => output.push_str(&value),
+            None => output.push_str(placeholder),
+        }
+        rest = &rest[start + end + 2..];
+    }
+    output.push_str(rest);
+    output
+}
+
+#[test]
+fn test_expand_facts() {
+    let cwd = env::current_dir().unwrap().display().to_string();
+    assert_eq!(
+        expand("Working in {{ cwd }}, see {{unknown}}"),
+        format!("Working in {cwd}, see {{{{unknown}}}}")
+    );
+    assert_eq!(expand("No placeholders {{"), "No placeholders {{");
+}
@@ -0,0 +1,218 @@
+use std::error::Error;
+use std::ops::Range;
+
+use chrono::Local;
+use crossterm::style::Stylize;
+
+use crate::session::{self, Session};
+
+/// Characters of context that are shown around a search hit
+const SNIPPET_CONTEXT: usize = 40;
+
+/// Maximum number of hits that are shown per session
+const MAX_HITS: usize = 3;
+
+/// Prints a single line describing the session
+fn print_entry(session: &Session) {
+    println!(
+        "{}  {}  {}  ({}, {} messages)",
+        session.id.as_str().dark_grey(),
+        session
+            .updated
+            .with_timezone(&Local)
+            .format("%Y-%m-%d %H:%M"),
+        session.title().bold(),
+        session.model,
+        session
+            .messages
+            .iter()
+            .filter(|m| m.role != "system")
+            .count(),
+    );
+}
+
+/// Lists all stored sessions, the most recent one last
+pub fn print_list() -> Result<(), Box<dyn Error>> {
+    let sessions = session::list()?;
+    if sessions.is_empty() {
+        println!("--- System: There are no stored sessions yet");
+    }
+    for session in sessions.iter() {
+        print_entry(session);
+    }
+    Ok(())
+}
+
+/// Finds `needle` in `haystack`, ignoring the case.
+///
+/// Returns the byte range of the match in `haystack`.
+fn find_ignore_case(haystack: &str, needle: &str) -> Option<Range<usize>> {
+    let needle: Vec<char> = needle.chars().flat_map(char::to_lowercase).collect();
+    if needle.is_empty() {
+        return None;
+    }
+    for (start, _) in haystack.char_indices() {
+        let mut remaining = needle.iter();
+        let mut end = start;
+        let mut matches = true;
+        for (i, c) in haystack[start..].char_indices() {
+            if remaining.len() == 0 {
+                break;
+            }
+            for lower in c.to_lowercase() {
+                if remaining.next().is_some_and(|n| *n != lower) {
+                    matches = false;
+                }
+            }
+            if !matches {
+                break;
+            }
+            end = start + i + c.len_utf8();
+        }
+        if matches && remaining.len() == 0 {
+            return Some(start..end);
+        }
+    }
+    None
+}
+
+/// Finds every whitespace-separated term of `query` in `haystack`, ignoring the case.
+///
+/// Returns the byte ranges of all terms (sorted), or `None` if any term is missing.
+fn find_terms(haystack: &str, query: &str) -> Option<Vec<Range<usize>>> {
+    let mut hits = query
+        .split_whitespace()
+        .map(|term| find_ignore_case(haystack, term))
+        .collect::<Option<Vec<_>>>()?;
+    if hits.is_empty() {
+        return None;
+    }
+    hits.sort_by_key(|hit| hit.start);
+    Some(hits)
+}
+
+/// Byte position `chars` characters before (or after) `pos`
+fn move_chars(content: &str, pos: usize, chars: usize, forward: bool) -> usize {
+    if forward {
+        content[pos..]
+            .char_indices()
+            .nth(chars)
+            .map_or(content.len(), |(i, _)| pos + i)
+    } else {
+        content[..pos]
+            .char_indices()
+            .rev()
+            .nth(chars.saturating_sub(1))
+            .map_or(0, |(i, _)| i)
+    }
+}
+
+/// Cuts a single line of text around the first hit and highlights all hits in it
+fn snippet(content: &str, hits: &[Range<usize>]) -> String {
+    let first = &hits[0];
+    let start = move_chars(content, first.start, SNIPPET_CONTEXT, false);
+    // Later hits are shown as well, if they are close enough
+    let end = hits
+        .iter()
+        .map(|hit| hit.end)
+        .filter(|end| content[first.start..*end].chars().count() <= 2 * SNIPPET_CONTEXT)
+        .max()
+        .unwrap_or(first.end);
+    let end = move_chars(content, end, SNIPPET_CONTEXT, true);
+
+    let mut snippet = String::new();
+    if start > 0 {
+        snippet.push('…');
+    }
+    let mut pos = start;
+    for hit in hits.iter().filter(|hit| hit.end <= end) {
+        // Overlapping terms are highlighted once
+        if hit.start < pos {
+            continue;
+        }
+        snippet.push_str(&content[pos..hit.start]);
+        snippet.push_str(&content[hit.clone()].to_string().yellow().bold().to_string());
+        pos = hit.end;
+    }
+    snippet.push_str(&content[pos..end]);
+    if end < content.len() {
+        snippet.push('…');
+    }
+    snippet.replace('\n', " ")
+}
+
+/// Searches all stored sessions for `query` and prints the matching sessions with snippets
+pub fn search(query: &str) -> Result<(), Box<dyn Error>> {
+    let mut found = false;
+    for session in session::list()?.iter() {
+        let hits: Vec<String> = session
+            .messages
+            .iter()
+            .filter(|m| m.role != "system")
+            .filter_map(|m| {
+                find_terms(&m.content, query)
+                    .map(|hits| format!("{}: {}", m.role, snippet(&m.content, &hits)))
+            })
+            .collect();
+        if hits.is_empty() {
+            continue;
+        }
+        found = true;
+        print_entry(session);
+        for hit in hits.iter().take(MAX_HITS) {
+            println!("    {hit}");
+        }
+        if hits.len() > MAX_HITS {
+            println!("    … and {} more", hits.len() - MAX_HITS);
+        }
+    }
+    if !found {
+        println!("--- System: Nothing found for '{query}'");
+    }
+    Ok(())
+}
+
+/// Runs `rgpt history [search <query>]` (or `/history` in a conversation)
+pub fn run(args: &[String]) -> Result<(), Box<dyn Error>> {
+    match args.split_first() {
+        None => print_list(),
+        Some((command, query)) if command == "search" && !query.is_empty() => {
+            search(&query.join(" "))
+        }
+        _ => Err("Usage: history [search <query>]".into()),
+    }
+}
+
+#[test]
+fn test_find_ignore_case() {
+    assert_eq!(find_ignore_case("use Tokio::select!", "tokio select"), None);
+    assert_eq!(
+        find_ignore_case("use Tokio select!", "tokio select"),
+        Some(4..16)
+    );
+    assert_eq!(find_ignore_case("Grüße aus KÖLN", "köln"), Some(12..17));
+    assert_eq!(find_ignore_case("abc", ""), None);
+}
+
+#[test]
+fn test_find_terms() {
+    assert_eq!(
+        find_terms("use tokio::select! here", "tokio select"),
+        Some(vec![4..9, 11..17])
+    );
+    assert_eq!(
+        find_terms("SELECT from tokio", "tokio select"),
+        Some(vec![0..6, 12..17])
+    );
+    assert_eq!(find_terms("use tokio::join!", "tokio select"), None);
+    assert_eq!(find_terms("abc", "  "), None);
+    let hits = find_terms("use tokio::select! here", "tokio select").unwrap();
+    assert_eq!(
+        snippet("use tokio::select! here", &hits),
+        format!(
+            "use {}::{}! here",
+            "tokio".yellow().bold(),
+            "select".yellow().bold()
+        )
+    );
+}
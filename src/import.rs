@@ -0,0 +1,245 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::Path;
+
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use serde_json::Value;
+use zip::ZipArchive;
+
+use crate::client::{Msg, Usage};
+use crate::session::{self, Session};
+
+/// A conversation from the `conversations.json` of a ChatGPT data export
+#[derive(Debug, Deserialize)]
+struct Conversation {
+    title: Option<String>,
+    create_time: Option<f64>,
+    update_time: Option<f64>,
+    /// All messages of the conversation, as a tree (edits and regenerated answers are branches)
+    mapping: HashMap<String, Node>,
+    /// The last message of the branch that was shown in the web ui
+    current_node: Option<String>,
+    default_model_slug: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Node {
+    message: Option<Message>,
+    parent: Option<String>,
+    #[serde(default)]
+    children: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Message {
+    author: Author,
+    content: Content,
+    create_time: Option<f64>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Author {
+    role: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct Content {
+    content_type: String,
+    /// Mostly strings, but images and other attachments are objects
+    #[serde(default)]
+    parts: Vec<Value>,
+}
+
+fn timestamp(secs: f64) -> Option<DateTime<Utc>> {
+    DateTime::from_timestamp(secs.trunc() as i64, (secs.fract() * 1e9) as u32)
+}
+
+impl Conversation {
+    /// Ids of the nodes on the displayed branch, from the root to the last message
+    fn branch(&self) -> Vec<&str> {
+        // Without a current node, we follow the latest child from the root
+        let leaf = self.current_node.clone().or_else(|| {
+            let mut id = self.mapping.iter().find(|(_, n)| n.parent.is_none())?.0;
+            while let Some(child) = self.mapping.get(id)?.children.last() {
+                id = child;
+            }
+            Some(id.clone())
+        });
+        let mut branch = Vec::new();
+        let mut next = leaf
+            .as_deref()
+            .and_then(|id| self.mapping.get_key_value(id));
+        while let Some((id, node)) = next {
+            branch.push(id.as_str());
+            next = node
+                .parent
+                .as_deref()
+                .and_then(|id| self.mapping.get_key_value(id));
+        }
+        branch.reverse();
+        branch
+    }
+
+    fn into_session(self) -> Session {
+        let mut messages = Vec::new();
+        for id in self.branch() {
+            let Some(message) = self.mapping.get(id).and_then(|n| n.message.as_ref()) else {
+                continue;
+            };
+            let role = message.author.role.as_str();
+            // Tool calls, code interpreter output etc. can't be continued here
+            if !matches!(role, "system" | "user" | "assistant")
+                || !matches!(
+                    message.content.content_type.as_str(),
+                    "text" | "multimodal_text"
+                )
+            {
+                continue;
+            }
+            let content = message
+                .content
+                .parts
+                .iter()
+                .filter_map(Value::as_str)
+                .collect::<Vec<_>>()
+                .join("\n");
+            if content.trim().is_empty() {
+                continue;
+            }
+            messages.push(Msg {
+                role: role.to_string(),
+                content,
+                timestamp: message.create_time.and_then(timestamp),
+                pinned: false,
+            });
+        }
+        let created = self
+            .create_time
+            .and_then(timestamp)
+            .unwrap_or_else(Utc::now);
+        let updated = self.update_time.and_then(timestamp).unwrap_or(created);
+        Session {
+            // Derived from the creation time (in UTC, so it doesn't depend on the time zone),
+            // so importing twice doesn't duplicate anything
+            id: created.format("%Y%m%d-%H%M%S-%3f").to_string(),
+            title: self.title,
+            forked_from: None,
+            created,
+            updated,
+            model: self
+                .default_model_slug
+                .unwrap_or_else(|| "chatgpt".to_string()),
+            messages,
+            usage: Usage::default(),
+        }
+    }
+}
+
+/// Whether messages were added to (or changed in) the `stored` session, that are not part of the `imported` one
+fn continued_locally(stored: &Session, imported: &Session) -> bool {
+    // Resuming a session adds a system prompt, which doesn't count
+    let messages = |session: &Session| -> Vec<(String, String)> {
+        session
+            .messages
+            .iter()
+            .filter(|m| m.role != "system")
+            .map(|m| (m.role.clone(), m.content.clone()))
+            .collect()
+    };
+    !messages(imported).starts_with(&messages(stored))
+}
+
+/// Whether `file` looks like a ChatGPT data export (the zip file, or its `conversations.json`)
+pub fn is_export(file: &str) -> bool {
+    Path::new(file)
+        .extension()
+        .is_some_and(|ext| ext == "zip" || ext == "json")
+}
+
+/// Reads `conversations.json` - either directly, or from the zip file of the export
+fn read_export(path: &Path) -> Result<Vec<u8>, Box<dyn Error>> {
+    if path.extension().is_some_and(|ext| ext == "zip") {
+        let mut archive = ZipArchive::new(File::open(path)?)?;
+        let mut file = archive.by_name("conversations.json")?;
+        let mut content = Vec::new();
+        file.read_to_end(&mut content)?;
+        Ok(content)
+    } else {
+        Ok(fs::read(path)?)
+    }
+}
+
+/// Runs `rgpt import <chatgpt-export.zip|conversations.json>`
+pub fn run(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let [path] = args else {
+        return Err("Usage: rgpt import <chatgpt-export.zip|conversations.json>".into());
+    };
+    let conversations: Vec<Conversation> = serde_json::from_slice(&read_export(Path::new(path))?)?;
+    let mut imported = 0;
+    let mut skipped = 0;
+    for conversation in conversations {
+        let session = conversation.into_session();
+        if session.messages.iter().all(|m| m.role != "user") {
+            continue;
+        }
+        // Importing again updates the session - unless it was continued here in the meantime
+        if session.path()?.exists() && continued_locally(&session::load(&session.id)?, &session) {
+            println!(
+                "{}  {}  (skipped - continued since the last import)",
+                session.id,
+                session.title()
+            );
+            skipped += 1;
+            continue;
+        }
+        session.write()?;
+        println!("{}  {}", session.id, session.title());
+        imported += 1;
+    }
+    println!(
+        "--- System: Imported {imported} conversations - continue one with 'rgpt --session <id>'"
+    );
+    if skipped > 0 {
+        println!("--- System: Skipped {skipped} conversations, that were continued since the last import");
+    }
+    Ok(())
+}
+
+#[test]
+fn test_import_follows_current_branch() {
+    let export = r#"[{
+        "title": "Borrow checker",
+        "create_time": 1700000000.5,
+        "update_time": 1700000100.0,
+        "current_node": "a2",
+        "default_model_slug": "gpt-4",
+        "mapping": {
+            "root": {"id": "root", "message": null, "parent": null, "children": ["sys"]},
+            "sys": {"id": "sys", "message": {"author": {"role": "system"}, "content": {"content_type": "text", "parts": [""]}}, "parent": "root", "children": ["u1"]},
+            "u1": {"id": "u1", "message": {"author": {"role": "user"}, "content": {"content_type": "text", "parts": ["Why?"]}, "create_time": 1700000001.0}, "parent": "sys", "children": ["a1", "a2"]},
+            "a1": {"id": "a1", "message": {"author": {"role": "assistant"}, "content": {"content_type": "text", "parts": ["Old answer"]}}, "parent": "u1", "children": []},
+            "a2": {"id": "a2", "message": {"author": {"role": "assistant"}, "content": {"content_type": "text", "parts": ["New answer"]}}, "parent": "u1", "children": []}
+        }
+    }]"#;
+    let mut conversations: Vec<Conversation> = serde_json::from_str(export).unwrap();
+    let session = conversations.remove(0).into_session();
+    let contents: Vec<&str> = session
+        .messages
+        .iter()
+        .map(|m| m.content.as_str())
+        .collect();
+    assert_eq!(contents, ["Why?", "New answer"]);
+    assert_eq!(session.title.as_deref(), Some("Borrow checker"));
+    assert_eq!(session.model, "gpt-4");
+    assert_eq!(session.id, "20231114-221320-500");
+
+    let mut continued = session.clone();
+    continued
+        .messages
+        .push(Msg::new("user", "And now?".to_string()));
+    assert!(continued_locally(&continued, &session));
+    assert!(!continued_locally(&session, &session));
+}
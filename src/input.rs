@@ -3,9 +3,8 @@ use std::io::{stdout, Write};
 use crossterm::{
     cursor::{MoveLeft, MoveToNextLine},
     event::{Event, EventStream, KeyCode, KeyEvent, KeyModifiers},
-    queue,
     style::Print,
-    terminal::{disable_raw_mode, enable_raw_mode, size, Clear, ClearType, ScrollDown, ScrollUp},
+    terminal::{disable_raw_mode, enable_raw_mode, Clear, ClearType},
     QueueableCommand,
 };
 use futures::{FutureExt, StreamExt};
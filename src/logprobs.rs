@@ -0,0 +1,66 @@
+use std::io::{stdout, Write};
+
+use crossterm::{
+    queue,
+    style::{PrintStyledContent, Stylize},
+};
+
+use crate::client::TokenLogprob;
+
+/// How many of the least confident tokens are listed after an answer
+const SUMMARY_LEN: usize = 5;
+
+/// Prints a single token, colored by how confident the model was about it
+pub fn print_token(token: &TokenLogprob) -> Result<(), std::io::Error> {
+    let p = token.probability();
+    let content = token.token.clone();
+    let styled = if p >= 0.9 {
+        content.green()
+    } else if p >= 0.5 {
+        content.yellow()
+    } else {
+        content.red()
+    };
+    let mut stdout = stdout();
+    queue!(stdout, PrintStyledContent(styled))?;
+    stdout.flush()
+}
+
+/// Lists the positions of the tokens the model was least sure about
+pub fn print_summary(tokens: &[TokenLogprob]) {
+    let mut positions: Vec<usize> = (0..tokens.len()).collect();
+    positions.sort_by(|a, b| tokens[*a].logprob.total_cmp(&tokens[*b].logprob));
+    println!("--- System: Least confident tokens (use '/why N' to see alternatives):");
+    for n in positions.into_iter().take(SUMMARY_LEN) {
+        let token = &tokens[n];
+        println!(
+            "- #{:<5} {:?} ({:.1}%)",
+            n + 1,
+            token.token,
+            100.0 * token.probability()
+        );
+    }
+}
+
+/// Shows the alternatives the model considered for the token at position `n` (1-based)
+pub fn print_alternatives(tokens: &[TokenLogprob], n: usize) {
+    let Some(token) = n.checked_sub(1).and_then(|i| tokens.get(i)) else {
+        println!(
+            "--- System: There is no token #{n} - the last answer has {} tokens",
+            tokens.len()
+        );
+        return;
+    };
+    println!(
+        "--- System: Token #{n} {:?} ({:.1}%)",
+        token.token,
+        100.0 * token.probability()
+    );
+    for alternative in token.top_logprobs.iter() {
+        println!(
+            "- {:?} ({:.1}%)",
+            alternative.token,
+            100.0 * alternative.logprob.exp()
+        );
+    }
+}
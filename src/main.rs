@@ -1,5 +1,19 @@
+mod attach;
+mod cargo;
 mod client;
+mod compare;
+mod config;
+mod crypto;
+mod export;
+mod facts;
+mod history;
+mod import;
 mod input;
+mod logprobs;
+mod persona;
+mod run;
+mod session;
+mod template;
 // Alright boy - step 1,
 //
 // build something you can type a prompt into
@@ -13,16 +27,20 @@ mod input;
 // success
 
 use crate::client::GptClient;
-use client::{Input, Output, UseContext};
+use crate::config::{Config, Project};
+use crate::persona::Persona;
+use client::{Input, Output, Switch, TokenLogprob};
 use input::get_user_input;
 use markdown::mdast::Node;
 use pulldown_cmark_mdcat::resources::NoopResourceHandler;
 use pulldown_cmark_mdcat::{Environment, Settings, TerminalSize};
+use session::Session;
 use std::env;
-use std::io::stdout;
-use std::path::Path;
-use std::{error::Error, io::stdin};
+use std::error::Error;
+use std::io::{stdout, Write};
+use std::path::{Path, PathBuf};
 use tokio::sync::mpsc::Sender;
+use tokio::sync::oneshot;
 use tokio::{spawn, sync::mpsc};
 
 use markdown::{to_mdast, ParseOptions};
@@ -54,12 +72,24 @@ impl MdPrinter {
     }
 
     pub fn print(&self, input: impl AsRef<str>) -> Result<(), Box<dyn Error>> {
-        let mut terminal_size = TerminalSize::detect().unwrap();
-        terminal_size.columns = if terminal_size.columns / 2 < 77 {
-            3 * terminal_size.columns / 4
+        let columns = TerminalSize::detect().unwrap_or_default().columns;
+        let columns = if columns / 2 < 77 {
+            3 * columns / 4
         } else {
-            terminal_size.columns / 2
+            columns / 2
         };
+        self.write(&mut stdout(), input, columns)
+    }
+
+    /// Renders the markdown `input` into `writer`, using at most `columns` characters per line
+    pub fn write(
+        &self,
+        writer: &mut impl Write,
+        input: impl AsRef<str>,
+        columns: u16,
+    ) -> Result<(), Box<dyn Error>> {
+        let mut terminal_size = TerminalSize::detect().unwrap_or_default();
+        terminal_size.columns = columns;
         let settings = Settings {
             terminal_capabilities: self.tp.capabilities(),
             terminal_size,
@@ -71,7 +101,7 @@ impl MdPrinter {
             &settings,
             &self.environment,
             &self.rs_handler,
-            &mut stdout(),
+            writer,
             parser,
         )?;
         Ok(())
@@ -86,58 +116,521 @@ impl MdPrinter {
 //     Ok(input)
 // }
 
-async fn process_input(input: &str, input_tx: &Sender<Input>) -> Result<bool, Box<dyn Error>> {
+/// Everything the user interface has to remember between two questions
+struct State {
+    /// Render answers token by token, colored by their probability
+    logprobs: bool,
+    /// Tokens of the last answer (only if logprobs are enabled)
+    last_tokens: Vec<TokenLogprob>,
+    /// Name of the active persona (empty for an ad-hoc system prompt)
+    persona: String,
+    /// Whether a new system prompt replaces the old one retroactively or from now on
+    switch: Switch,
+    /// Output of commands (see '/run'), that is added to the next question
+    command_output: Vec<String>,
+}
+
+/// Puts the output of all commands that ran since the last question in front of `question`
+fn with_command_output(question: String, state: &mut State) -> String {
+    if state.command_output.is_empty() {
+        return question;
+    }
+    let mut context = std::mem::take(&mut state.command_output).join("\n\n");
+    context.push_str("\n\n");
+    context.push_str(&question);
+    context
+}
+
+/// Switches to the persona with the given name
+async fn switch_persona(
+    name: &str,
+    input_tx: &Sender<Input>,
+    state: &mut State,
+) -> Result<(), Box<dyn Error>> {
+    match persona::find(name) {
+        Some(persona) => {
+            println!("--- System: Using '{}' context", persona.name);
+            state.persona = persona.name.clone();
+            input_tx.send(Input::Persona(persona)).await?;
+        }
+        None => println!("--- System: Unknown persona '{name}' - see '/personas'"),
+    }
+    Ok(())
+}
+
+/// Prints the active system prompt, and where it applies
+fn print_system(session: &Session) {
+    let Some(i) = session.messages.iter().position(|m| m.role == "system") else {
+        println!("--- System: There is no system prompt");
+        return;
+    };
+    if i == 0 {
+        println!("--- System: The system prompt is:");
+    } else {
+        println!("--- System: Since message {}, the system prompt is:", i + 1);
+    }
+    println!("{}", session.messages[i].content);
+}
+
+/// Lists all available personas
+fn print_personas(active: &str) {
+    for persona in persona::load_all() {
+        let marker = if persona.name == active { "*" } else { " " };
+        let model = persona
+            .model
+            .map(|model| format!(" ({model})"))
+            .unwrap_or_default();
+        println!(
+            "{marker} {:<14} {}{model}",
+            persona.name, persona.description
+        );
+    }
+}
+
+/// Prints a stored conversation, like it was shown when it happened
+fn print_transcript(md: &MdPrinter, session: &Session) -> Result<(), Box<dyn Error>> {
+    for msg in session.messages.iter() {
+        let header = match msg.role.as_str() {
+            "user" => "# Input",
+            "assistant" => "# ChatGPT",
+            _ => continue,
+        };
+        println!();
+        md.print(header)?;
+        md.print(&msg.content)?;
+    }
+    Ok(())
+}
+
+/// Restores a stored session in the client and shows what was said so far
+async fn resume(
+    md: &MdPrinter,
+    session: Session,
+    input_tx: &Sender<Input>,
+) -> Result<(), Box<dyn Error>> {
+    print_transcript(md, &session)?;
+    println!();
+    println!(
+        "--- System: Continuing session {} ({} messages)",
+        session.id,
+        session.messages.len()
+    );
+    input_tx.send(Input::Load(Box::new(session))).await?;
+    Ok(())
+}
+
+/// Asks the client for a copy of the current conversation
+async fn snapshot(input_tx: &Sender<Input>) -> Result<Session, Box<dyn Error>> {
+    let (tx, rx) = oneshot::channel();
+    input_tx.send(Input::Snapshot(tx)).await?;
+    Ok(rx.await?)
+}
+
+/// Lists the messages of the conversation with their number (or only the pinned ones)
+fn print_messages(session: &Session, only_pinned: bool) {
+    if only_pinned && !session.messages.iter().any(|m| m.pinned) {
+        println!("--- System: There are no pinned messages - pin one with '/pin N'");
+    }
+    for (i, msg) in session.messages.iter().enumerate() {
+        if only_pinned && !msg.pinned {
+            continue;
+        }
+        let line = msg.content.lines().next().unwrap_or_default();
+        let mut preview: String = line.chars().take(70).collect();
+        if line.chars().count() > 70 || msg.content.lines().nth(1).is_some() {
+            preview.push('…');
+        }
+        let pin = if msg.pinned { "*" } else { " " };
+        println!("{:>3}{pin} {:<9}  {preview}", i + 1, msg.role);
+    }
+}
+
+/// Pins or unpins message `arg` (or the last message, if `arg` is empty)
+async fn pin(arg: &str, pinned: bool, input_tx: &Sender<Input>) -> Result<(), Box<dyn Error>> {
+    let session = snapshot(input_tx).await?;
+    let n = match arg {
+        "" => session.messages.len(),
+        arg => arg.parse().unwrap_or(0),
+    };
+    match n.checked_sub(1).and_then(|i| session.messages.get(i)) {
+        // There is only ever one system prompt, and it stays anyway
+        Some(msg) if msg.role == "system" => {
+            println!("--- System: Message {n} is the system prompt, which always stays")
+        }
+        Some(msg) if msg.pinned == pinned => {
+            let state = if pinned { "pinned" } else { "not pinned" };
+            println!("--- System: Message {n} is already {state}");
+        }
+        Some(_) => {
+            let action = if pinned { "Pinned" } else { "Unpinned" };
+            println!("--- System: {action} message {n}");
+            input_tx.send(Input::Pin(n - 1, pinned)).await?;
+        }
+        None => println!("--- System: There is no message {n} - see '/show' for all messages"),
+    }
+    Ok(())
+}
+
+/// Runs `rgpt export <session> [file]`
+fn export_session(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let (id, file) = match args {
+        [id] => (id, None),
+        [id, file] => (id, Some(PathBuf::from(file))),
+        _ => return Err("Usage: rgpt export <session> [file.md|file.html|file.json]".into()),
+    };
+    let session = session::load(id)?;
+    match file {
+        Some(path) => export::write(&session, &path)?,
+        None => print!("{}", export::to_markdown(&session)),
+    }
+    Ok(())
+}
+
+async fn process_input(
+    input: &str,
+    md: &MdPrinter,
+    input_tx: &Sender<Input>,
+    state: &mut State,
+) -> Result<bool, Box<dyn Error>> {
     let print_help = || {
         println!("-- Basic commands:");
         println!("- Showing this help screen - '/help' or '/h'");
         println!("- Clearing conversation    - '/clear' or '/c' or '/new' or '/n'");
+        println!("- Continue cut off answer  - '/continue' or '/cont'");
+        println!("- Show numbered messages   - '/show'");
+        println!("- Undo last question       - '/undo'");
+        println!("- Roll back to message N   - '/rewind N'");
+        println!("- Pin message N (or last)  - '/pin [N]'");
+        println!("- Unpin message N          - '/unpin N'");
+        println!("- List pinned messages     - '/pins'");
         println!("- Quit program             - '/quit' or '/q' or '/exit' or '/stop'");
         println!();
+        println!("-- Attach files:");
+        println!("- Whole file      - '@src/main.rs'");
+        println!("- Line 40 - 80    - '@src/main.rs:40-80'");
+        println!("- Whole directory - '@src/'");
+        println!("- Matching files  - '@src/**/*.rs'");
+        println!();
+        println!("-- Command output:");
+        println!("- Run a command and add its output to the next question - '/run <command>'");
+        println!();
+        println!("-- Templates:");
+        println!("- List templates         - '/t'");
+        println!("- Send a filled template - '/t <name> key=value ...'");
+        println!();
+        println!("-- Sessions:");
+        println!("- List stored sessions      - '/history'");
+        println!("- Search stored sessions    - '/history search <query>'");
+        println!("- Continue a stored session - '/load <id>'");
+        println!("- Continue in a copy        - '/fork'");
+        println!("- Export this conversation  - '/export <file.md|file.html|file.json>'");
+        println!();
+        println!("-- Multiple answers:");
+        println!("- Request N answers per question - '/choices N'");
+        println!();
+        println!("-- Compare models:");
+        println!("- Ask several models at once - '/compare gpt-4o,gpt-4o-mini'");
+        println!("- Back to a single model     - '/compare off'");
+        println!();
+        println!("-- Token probabilities:");
+        println!("- Toggle confidence colors - '/logprobs'");
+        println!("- Alternatives for token N - '/why N'");
+        println!();
         println!("-- Change context:");
         println!("- Basic (standard chatgpt-context)         - '/basic' or '/b'");
         println!("- Short (shorter, more direct answers)     - '/short' or '/s'");
         println!("- Programming (fine tuned for programmers) - '/programming' or '/prog' or '/p'");
+        println!("- Any other persona                        - '/persona <name>'");
+        println!("- List all personas                        - '/personas'");
+        println!("- Use an ad-hoc system prompt              - '/system <text>'");
+        println!("- Show the current system prompt           - '/system'");
+        println!("- Apply switches to the whole conversation - '/switch retroactive'");
+        println!("- Apply switches only to following answers - '/switch from-now-on'");
         println!();
+        println!("Add your own personas as markdown files to ~/.config/rgpt/personas/");
         println!("You can set the default context via environment variable RGPT_CONTEXT='basic'");
+        println!("or with 'context = \"basic\"' in ~/.config/rgpt/config.toml");
     };
     // add some commands here
     if input.starts_with('/') {
-        match input.trim().to_lowercase().as_str() {
+        let (command, arg) = input
+            .trim()
+            .split_once(char::is_whitespace)
+            .map(|(command, arg)| (command, arg.trim()))
+            .unwrap_or((input.trim(), ""));
+        match command.to_lowercase().as_str() {
             "/exit" | "/quit" | "/q" | "/stop" => std::process::exit(0),
             "/help" | "/h" => print_help(),
             "/programming" | "/prog" | "/p" => {
-                println!("--- System: Using 'programming' context");
-                input_tx
-                    .send(Input::Context(UseContext::Programming))
-                    .await?
+                switch_persona("programming", input_tx, state).await?
             }
-            "/short" | "/s" => {
-                println!("--- System: Using 'short' context");
-                input_tx.send(Input::Context(UseContext::Short)).await?;
+            "/short" | "/s" => switch_persona("short", input_tx, state).await?,
+            "/basic" | "/b" => switch_persona("basic", input_tx, state).await?,
+            "/persona" if arg.is_empty() => println!("--- System: Usage: '/persona <name>'"),
+            "/persona" => switch_persona(arg, input_tx, state).await?,
+            "/personas" => print_personas(&state.persona),
+            "/system" if arg.is_empty() => print_system(&snapshot(input_tx).await?),
+            "/system" => {
+                println!("--- System: Using a custom system prompt");
+                state.persona = String::new();
+                input_tx.send(Input::System(arg.to_string())).await?;
             }
-            "/basic" | "/b" => {
-                println!("--- System: Using 'basic' context");
-                input_tx.send(Input::Context(UseContext::Basic)).await?;
+            "/switch" => {
+                match arg {
+                    "retroactive" | "all" => state.switch = Switch::Retroactive,
+                    "from-now-on" | "now" => state.switch = Switch::FromNowOn,
+                    "" => (),
+                    _ => println!(
+                        "--- System: Usage: '/switch retroactive' or '/switch from-now-on'"
+                    ),
+                }
+                match state.switch {
+                    Switch::Retroactive => println!("--- System: A new system prompt replaces the old one for the whole conversation"),
+                    Switch::FromNowOn => println!("--- System: A new system prompt only applies to the following answers"),
+                }
+                input_tx.send(Input::Switch(state.switch)).await?;
             }
             "/clear" | "/c" | "/new" | "/n" => input_tx.send(Input::Clear).await?,
+            "/continue" | "/cont" => {
+                // The continuation is streamed like a normal answer
+                input_tx.send(Input::Continue).await?;
+                return Ok(false);
+            }
+            "/logprobs" => {
+                state.logprobs = !state.logprobs;
+                let status = if state.logprobs {
+                    "enabled"
+                } else {
+                    "disabled"
+                };
+                println!("--- System: Token probabilities {status}");
+                input_tx.send(Input::Logprobs(state.logprobs)).await?;
+            }
+            "/choices" => match arg.parse::<usize>() {
+                Ok(n) if n > 0 => {
+                    println!("--- System: Requesting {n} answer(s) per question");
+                    input_tx.send(Input::Choices(n)).await?;
+                }
+                _ => println!("--- System: Usage: '/choices N' with N > 0"),
+            },
+            "/t" | "/template" if arg.is_empty() => match template::list() {
+                Ok(names) if names.is_empty() => {
+                    println!("--- System: There are no templates in ~/.config/rgpt/templates/ yet")
+                }
+                Ok(names) => println!("--- System: Templates: {}", names.join(", ")),
+                Err(e) => println!("--- System: Failed to list templates: {e}"),
+            },
+            "/t" | "/template" => {
+                let mut args = arg.split_whitespace().map(str::to_string);
+                let name = args.next().unwrap_or_default();
+                match template::expand(&name, &args.collect::<Vec<_>>()) {
+                    Ok(text) => {
+                        println!("--- System: Sending template '{name}'");
+                        let text = with_command_output(text, state);
+                        input_tx.send(Input::Text(text)).await?;
+                        return Ok(false);
+                    }
+                    Err(e) => println!("--- System: {e}"),
+                }
+            }
+            "/run" if arg.is_empty() => println!("--- System: Usage: '/run <command>'"),
+            "/run" => match run::run(arg).await {
+                Ok(output) => state.command_output.push(output),
+                Err(e) => println!("--- System: Failed to run '{arg}': {e}"),
+            },
+            "/history" => {
+                let args: Vec<String> = arg.split_whitespace().map(str::to_string).collect();
+                if let Err(e) = history::run(&args) {
+                    println!("--- System: {e}");
+                }
+            }
+            "/export" if arg.is_empty() => {
+                println!("--- System: Usage: '/export <file.md|file.html|file.json>'")
+            }
+            "/export" => {
+                let session = snapshot(input_tx).await?;
+                match export::write(&session, Path::new(arg)) {
+                    Ok(()) => println!("--- System: Exported conversation to {arg}"),
+                    Err(e) => println!("--- System: Failed to export conversation: {e}"),
+                }
+            }
+            "/show" => print_messages(&snapshot(input_tx).await?, false),
+            "/pins" => print_messages(&snapshot(input_tx).await?, true),
+            "/pin" => pin(arg, true, input_tx).await?,
+            "/unpin" => pin(arg, false, input_tx).await?,
+            "/undo" => {
+                let session = snapshot(input_tx).await?;
+                match session.messages.iter().rposition(|m| m.role == "user") {
+                    Some(last_question) => {
+                        println!("--- System: Removed the last question and its answer");
+                        input_tx.send(Input::Truncate(last_question)).await?;
+                    }
+                    None => println!("--- System: Nothing to undo"),
+                }
+            }
+            "/rewind" => {
+                let session = snapshot(input_tx).await?;
+                match arg.parse::<usize>() {
+                    Ok(n) if (1..=session.messages.len()).contains(&n) => {
+                        println!(
+                            "--- System: Rolled back to message {n}, removed {} messages",
+                            session.messages.len() - n
+                        );
+                        input_tx.send(Input::Truncate(n)).await?;
+                    }
+                    _ => println!(
+                        "--- System: Usage: '/rewind N' with N between 1 and {} (see '/show')",
+                        session.messages.len()
+                    ),
+                }
+            }
+            "/fork" => {
+                let (tx, rx) = oneshot::channel();
+                input_tx.send(Input::Fork(tx)).await?;
+                let fork = rx.await?;
+                println!(
+                    "--- System: Continuing in session {} (forked from {})",
+                    fork.id,
+                    fork.forked_from.unwrap_or_default()
+                );
+            }
+            "/load" if arg.is_empty() => println!("--- System: Usage: '/load <id>'"),
+            "/load" => match session::load(arg) {
+                Ok(session) => resume(md, session, input_tx).await?,
+                Err(e) => println!("--- System: Failed to load session: {e}"),
+            },
+            "/compare" => {
+                let models = compare::parse_models(arg);
+                if models.is_empty() {
+                    println!("--- System: Comparison disabled");
+                } else {
+                    println!("--- System: Comparing {}", models.join(", "));
+                }
+                input_tx.send(Input::Compare(models)).await?;
+            }
+            "/why" => match arg.parse() {
+                Ok(n) => logprobs::print_alternatives(&state.last_tokens, n),
+                Err(_) if state.last_tokens.is_empty() => {
+                    println!("--- System: No token probabilities available - enable them with '/logprobs'")
+                }
+                Err(_) => logprobs::print_summary(&state.last_tokens),
+            },
             _other => println!("--- System: Invalid input."),
         }
         return Ok(true);
     }
-    input_tx.send(Input::Text(input.to_string())).await?;
+    let input = match attach::attach(input) {
+        Ok((input, attachments)) => {
+            for attachment in attachments.iter() {
+                println!("--- System: Attached {}", attachment.describe());
+            }
+            input
+        }
+        Err(e) => {
+            println!("--- System: {e}");
+            return Ok(true);
+        }
+    };
+    let input = with_command_output(input, state);
+    input_tx.send(Input::Text(input)).await?;
     Ok(false)
 }
 
+/// Shows the alternative answers and lets the user decide which one to keep
+async fn pick_answer(
+    md: &MdPrinter,
+    alternatives: &[String],
+    input_tx: &Sender<Input>,
+) -> Result<(), Box<dyn Error>> {
+    let total = alternatives.len() + 1;
+    for (i, answer) in alternatives.iter().enumerate() {
+        println!();
+        md.print(format!("# ChatGPT ({}/{total})", i + 2))?;
+        md.print(answer)?;
+    }
+    println!();
+    println!("--- System: Which answer do you want to keep? [1-{total}, default 1]");
+    loop {
+        let input = get_user_input().await?;
+        let choice = match input.trim() {
+            "" => 1,
+            other => other.parse().unwrap_or(0),
+        };
+        if (1..=total).contains(&choice) {
+            println!();
+            println!("--- System: Keeping answer {choice}");
+            input_tx.send(Input::Pick(choice - 1)).await?;
+            return Ok(());
+        }
+        println!();
+        println!("--- System: Please enter a number between 1 and {total}");
+    }
+}
+
+/// Finds the project we are in, and the persona to start with
+fn startup_config() -> Result<(Option<Project>, Persona), String> {
+    let dir = Path::new(".").canonicalize().map_err(|e| e.to_string())?;
+    let project = Project::discover(&dir)?;
+    let persona = Config::load()?.default_persona(project.as_ref())?;
+    Ok((project, persona))
+}
+
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
+    // Subcommands, that don't start a conversation. Only their exact forms count,
+    // so a question like `rgpt history of async rust` is still sent as a question
+    let args: Vec<String> = env::args().skip(1).collect();
+    let command: Vec<&str> = args.iter().map(String::as_str).collect();
+    let result = match command.as_slice() {
+        ["history"] | ["history", "search", _, ..] => Some(history::run(&args[1..])),
+        ["export", id] | ["export", id, _] if session::exists(id) => {
+            Some(export_session(&args[1..]))
+        }
+        ["import", file] if import::is_export(file) => Some(import::run(&args[1..])),
+        ["encrypt"] => Some(session::encrypt_all()),
+        _ => None,
+    };
+    if let Some(result) = result {
+        if let Err(e) = result {
+            eprintln!("{e}");
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
     // Check API-Key
     if let Err(e) = env::var("OPENAI_KEY") {
         eprintln!("Failed to get OPENAI_KEY: {e}");
         std::process::exit(1);
     }
 
+    // The project we are in, and the context to start with
+    let (project, persona) = match startup_config() {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{e}");
+            std::process::exit(1);
+        }
+    };
+    if let Some(project) = &project {
+        println!(
+            "--- System: Using project config {}",
+            project.root.join(".rgpt.toml").display()
+        );
+    }
+    let persona_name = persona.name.clone();
+
     // Create a new client and spawn an event stream
-    let client = GptClient::new();
+    let client = GptClient::new(persona, project);
+
+    // Explain compiler diagnostics, instead of starting a conversation
+    if command.first() == Some(&"cargo") && cargo::is_invocation(&command[1..]) {
+        if let Err(e) = cargo::run(&args[1..], &client, &MdPrinter::new()?).await {
+            eprintln!("{e}");
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
     let (input_tx, input_rx) = mpsc::channel(16);
     let (output_tx, mut output_rx) = mpsc::channel(16);
     let _handle = spawn(client.event_stream(input_rx, output_tx));
@@ -145,20 +638,73 @@ async fn main() -> Result<(), Box<dyn Error>> {
     // Create markdown printer
     let md = MdPrinter::new()?;
 
-    // Parse input (if any)
-    let args: Vec<String> = env::args().collect();
-    let mut input = String::new();
-    if args.len() > 1 {
-        for word in args.iter().skip(1) {
-            input.push_str(word);
-            input.push(' ');
+    let mut state = State {
+        logprobs: client::logprobs_from_env(),
+        last_tokens: Vec::new(),
+        persona: persona_name,
+        switch: Switch::Retroactive,
+        command_output: Vec::new(),
+    };
+    println!("--- System: Using '{}' context", state.persona);
+
+    // Parse flags and input (if any)
+    let mut args = env::args().skip(1);
+    let mut words = Vec::new();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--compare" => {
+                let Some(models) = args.next() else {
+                    eprintln!("Usage: rgpt --compare gpt-4o,gpt-4o-mini [question]");
+                    std::process::exit(1);
+                };
+                input_tx
+                    .send(Input::Compare(compare::parse_models(&models)))
+                    .await?;
+            }
+            "--continue" => match session::latest() {
+                Ok(session) => resume(&md, session, &input_tx).await?,
+                Err(e) => {
+                    eprintln!("Failed to load the last session: {e}");
+                    std::process::exit(1);
+                }
+            },
+            "--session" => {
+                let Some(id) = args.next() else {
+                    eprintln!("Usage: rgpt --session <id> [question]");
+                    std::process::exit(1);
+                };
+                match session::load(&id) {
+                    Ok(session) => resume(&md, session, &input_tx).await?,
+                    Err(e) => {
+                        eprintln!("Failed to load session {id}: {e}");
+                        std::process::exit(1);
+                    }
+                }
+            }
+            "-t" | "--template" => {
+                let Some(name) = args.next() else {
+                    eprintln!("Usage: rgpt -t <template> [key=value ...]");
+                    std::process::exit(1);
+                };
+                // All remaining arguments belong to the template
+                let values: Vec<String> = args.by_ref().collect();
+                match template::expand(&name, &values) {
+                    Ok(text) => words.push(text),
+                    Err(e) => {
+                        eprintln!("{e}");
+                        std::process::exit(1);
+                    }
+                }
+            }
+            _ => words.push(arg),
         }
-        input.pop();
-    } else {
+    }
+    let mut input = words.join(" ");
+    if input.is_empty() {
         md.print("# Input")?;
         input = get_user_input().await?;
     };
-    while process_input(&input, &input_tx).await? {
+    while process_input(&input, &md, &input_tx, &mut state).await? {
         input = get_user_input().await?;
     }
 
@@ -174,20 +720,22 @@ async fn main() -> Result<(), Box<dyn Error>> {
         // Initialize last-children-len with 1, because we only print after having at least two nodes.
         let mut last_children_len = 1;
         let mut chunk_answer = String::with_capacity(1_000);
+        let mut alternatives: Vec<String> = Vec::new();
+        state.last_tokens.clear();
         // And await events from gpt-client
         while let Some(output) = output_rx.recv().await {
             match output {
+                // With logprobs, the answer is printed token by token instead
+                Output::Data(_) if state.logprobs => (),
                 Output::Data(answer) => {
                     full_answer.push_str(&answer);
                     match to_mdast(&full_answer, &ParseOptions::default()) {
-                        Ok(Node::Root(root)) => {
-                            if root.children.len() > last_children_len {
-                                // We are super sneaky, and just print each chunk,
-                                // whenever there is a new node in the root tree of our document.
-                                md.print(&chunk_answer)?;
-                                chunk_answer.clear(); // reset chunk
-                                last_children_len = root.children.len();
-                            }
+                        Ok(Node::Root(root)) if root.children.len() > last_children_len => {
+                            // We are super sneaky, and just print each chunk,
+                            // whenever there is a new node in the root tree of our document.
+                            md.print(&chunk_answer)?;
+                            chunk_answer.clear(); // reset chunk
+                            last_children_len = root.children.len();
                         }
                         Err(e) => {
                             println!("ERROR: Failed to parse - {e}");
@@ -196,19 +744,47 @@ async fn main() -> Result<(), Box<dyn Error>> {
                     }
                     chunk_answer.push_str(&answer);
                 }
+                Output::Alternative(index, answer) => {
+                    if index > alternatives.len() {
+                        alternatives.resize(index, String::new());
+                    }
+                    alternatives[index - 1].push_str(&answer);
+                }
+                Output::Tokens(tokens) => {
+                    for token in tokens.iter() {
+                        logprobs::print_token(token)?;
+                    }
+                    state.last_tokens.extend(tokens);
+                }
+                Output::Comparison(comparisons) => compare::print_columns(&md, &comparisons)?,
+                Output::Info(msg) | Output::Error(msg) => {
+                    // Show what we got so far, the client keeps the partial answer
+                    md.print(&chunk_answer)?;
+                    chunk_answer.clear();
+                    println!();
+                    println!("--- System: {msg}");
+                }
                 Output::End => {
                     md.print(&chunk_answer)?;
                     chunk_answer.clear();
                     full_answer.clear();
+                    if !state.last_tokens.is_empty() {
+                        println!();
+                        println!();
+                        logprobs::print_summary(&state.last_tokens);
+                    }
                     break;
                 }
             }
         }
+        if !alternatives.is_empty() {
+            pick_answer(&md, &alternatives, &input_tx).await?;
+        }
         println!();
         md.print("# Input")?;
         // Let's take another input
         input = get_user_input().await?;
-        while process_input(&input, &input_tx).await? {
+        while process_input(&input, &md, &input_tx, &mut state).await? {
             input = get_user_input().await?;
         }
     }
@@ -0,0 +1,163 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::config::config_dir;
+
+const BASIC_CONTEXT: &str = "You are a helpful assistant.";
+const NO_REPEAT: &str = "You are a helpful and very direct assistant.\
+                         You don't repeat the user's input in your answer,\
+                         you just provide a short and precise answer.";
+const PROGRAMMING: &str = "You are an assistant for a programmer.\
+                           You can assume basic knowledge about how to use the commandline \
+                           and an understanding of basic principles in programming languages.\
+                           In general, all your answers should assume, that the user is running {{os}} \
+                           with {{shell}} as shell. \
+                           The current directory is {{cwd}} (git branch: {{git_branch}}), today is {{date}}. \
+                           However, this should not change your answer related to non-computer issues.";
+
+/// A system prompt, together with the settings that should be used with it
+#[derive(Debug, Clone)]
+pub struct Persona {
+    pub name: String,
+    pub description: String,
+    pub prompt: String,
+    /// Overrides OPENAI_MODEL while the persona is active
+    pub model: Option<String>,
+    pub temperature: Option<f32>,
+}
+
+impl Persona {
+    fn builtin(name: &str, description: &str, prompt: &str) -> Self {
+        Persona {
+            name: name.to_string(),
+            description: description.to_string(),
+            prompt: prompt.to_string(),
+            model: None,
+            temperature: None,
+        }
+    }
+
+    /// Names are compared ignoring the case, so `Reviewer.md` is found as 'reviewer'
+    fn is_named(&self, name: &str) -> bool {
+        self.name.to_lowercase() == name.to_lowercase()
+    }
+
+    /// Parses a persona file - markdown with an optional front matter:
+    ///
+    /// ```text
+    /// ---
+    /// description: Reviews rust code
+    /// model: gpt-4o
+    /// temperature: 0.2
+    /// ---
+    /// You are a strict code reviewer ...
+    /// ```
+    ///
+    /// The name is taken from the front matter, or from the file name.
+    fn parse(name: &str, content: &str) -> Result<Self, String> {
+        let mut persona = Persona::builtin(name, "", content.trim());
+        let Some(rest) = content.strip_prefix("---") else {
+            return Ok(persona);
+        };
+        let Some((front_matter, prompt)) = rest.split_once("\n---") else {
+            return Err("front matter is not closed with '---'".to_string());
+        };
+        persona.prompt = prompt.trim().to_string();
+        for line in front_matter.lines().filter(|l| !l.trim().is_empty()) {
+            let Some((key, value)) = line.split_once(':') else {
+                return Err(format!("invalid line '{line}' in front matter"));
+            };
+            let value = value.trim().trim_matches('"').to_string();
+            match key.trim() {
+                "name" => persona.name = value,
+                "description" => persona.description = value,
+                "model" => persona.model = Some(value),
+                "temperature" => {
+                    let temperature = value
+                        .parse()
+                        .map_err(|_| format!("invalid temperature '{value}'"))?;
+                    persona.temperature = Some(temperature);
+                }
+                other => return Err(format!("unknown key '{other}' in front matter")),
+            }
+        }
+        if persona.prompt.is_empty() {
+            return Err("the system prompt is empty".to_string());
+        }
+        Ok(persona)
+    }
+}
+
+/// The personas that are always available
+pub fn builtins() -> Vec<Persona> {
+    vec![
+        Persona::builtin("basic", "Standard chatgpt-context", BASIC_CONTEXT),
+        Persona::builtin("short", "Shorter, more direct answers", NO_REPEAT),
+        Persona::builtin("programming", "Fine tuned for programmers", PROGRAMMING),
+    ]
+}
+
+fn load_dir(dir: &Path) -> io::Result<Vec<Persona>> {
+    let mut personas = Vec::new();
+    if !dir.exists() {
+        return Ok(personas);
+    }
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension().is_none_or(|ext| ext != "md") {
+            continue;
+        }
+        let name = path
+            .file_stem()
+            .map(|s| s.to_string_lossy().to_string())
+            .unwrap_or_default();
+        match Persona::parse(&name, &fs::read_to_string(&path)?) {
+            Ok(persona) => personas.push(persona),
+            Err(e) => eprintln!("--- System: Skipping persona {}: {e}", path.display()),
+        }
+    }
+    personas.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(personas)
+}
+
+/// All personas - the built-in ones, and those from `~/.config/rgpt/personas/*.md`.
+///
+/// A persona file with the name of a built-in persona replaces it.
+pub fn load_all() -> Vec<Persona> {
+    let mut personas = builtins();
+    let custom = config_dir()
+        .and_then(|dir| load_dir(&dir.join("personas")))
+        .unwrap_or_else(|e| {
+            eprintln!("--- System: Failed to load personas: {e}");
+            Vec::new()
+        });
+    for persona in custom {
+        personas.retain(|p| !p.is_named(&persona.name));
+        personas.push(persona);
+    }
+    personas
+}
+
+/// Finds the persona with the given name (ignoring the case)
+pub fn find(name: &str) -> Option<Persona> {
+    load_all().into_iter().find(|p| p.is_named(name))
+}
+
+#[test]
+fn test_parse_persona() {
+    let content = "---\ndescription: Translates to german\nmodel: gpt-4o-mini\ntemperature: 0.3\n---\nTranslate everything to german.\n";
+    let persona = Persona::parse("translator", content).unwrap();
+    assert_eq!(persona.name, "translator");
+    assert_eq!(persona.description, "Translates to german");
+    assert_eq!(persona.prompt, "Translate everything to german.");
+    assert_eq!(persona.model.as_deref(), Some("gpt-4o-mini"));
+    assert_eq!(persona.temperature, Some(0.3));
+
+    let plain = Persona::parse("sql", "You write SQL.").unwrap();
+    assert_eq!(plain.prompt, "You write SQL.");
+    assert!(Persona::parse("broken", "---\nfoo: bar\n---\nx").is_err());
+    assert!(Persona::parse("Reviewer", "Review.")
+        .unwrap()
+        .is_named("reviewer"));
+}
@@ -0,0 +1,118 @@
+use std::io::{self, Write};
+use std::path::Path;
+use std::process::Stdio;
+
+use tokio::io::{AsyncBufReadExt, AsyncRead, BufReader};
+use tokio::process::Command;
+
+use crate::attach::token_budget;
+use crate::template::fence;
+
+/// Runs `command` in the shell, with its output piped to us
+fn shell(command: &str) -> io::Result<tokio::process::Child> {
+    let mut shell = if cfg!(windows) {
+        let mut shell = Command::new("cmd");
+        shell.args(["/C", command]);
+        shell
+    } else {
+        let mut shell = Command::new("sh");
+        shell.args(["-c", command]);
+        shell
+    };
+    shell
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+}
+
+/// Shows the lines of `stream` as they arrive and collects them
+async fn forward(stream: impl AsyncRead + Unpin, mut terminal: impl Write) -> io::Result<Vec<u8>> {
+    let mut reader = BufReader::new(stream);
+    let mut collected = Vec::new();
+    let mut line = Vec::new();
+    while reader.read_until(b'\n', &mut line).await? > 0 {
+        terminal.write_all(&line)?;
+        terminal.flush()?;
+        collected.append(&mut line);
+    }
+    Ok(collected)
+}
+
+/// Splits `max_bytes` between stdout and stderr - half each, but a short stream leaves the rest to the other
+fn split_budget(stdout: usize, stderr: usize, max_bytes: usize) -> (usize, usize) {
+    let half = max_bytes / 2;
+    (
+        half.max(max_bytes.saturating_sub(stderr)),
+        half.max(max_bytes.saturating_sub(stdout)),
+    )
+}
+
+/// Keeps the end of `output`, if it doesn't fit into `max_bytes` - that's where errors usually are
+fn truncate(output: &str, max_bytes: usize) -> String {
+    if output.len() <= max_bytes {
+        return output.to_string();
+    }
+    let mut start = output.len() - max_bytes;
+    while !output.is_char_boundary(start) {
+        start += 1;
+    }
+    // Start with a complete line
+    let rest = &output[start..];
+    let rest = rest.split_once('\n').map_or(rest, |(_, rest)| rest);
+    format!("[... {} bytes omitted]\n{rest}", output.len() - rest.len())
+}
+
+/// Runs `command`, shows its output while it runs, and returns it as markdown for the next question
+pub async fn run(command: &str) -> io::Result<String> {
+    let mut child = shell(command)?;
+    let (Some(stdout), Some(stderr)) = (child.stdout.take(), child.stderr.take()) else {
+        return Err(io::Error::other("the output of the command is not piped"));
+    };
+    let (stdout, stderr, status) = tokio::try_join!(
+        forward(stdout, io::stdout()),
+        forward(stderr, io::stderr()),
+        child.wait()
+    )?;
+    let status = match status.code() {
+        Some(code) => format!("exit status {code}"),
+        None => "killed by a signal".to_string(),
+    };
+    println!();
+    println!("--- System: `{command}` finished with {status} - the output is added to your next question");
+
+    // The output is limited by the same budget as attached files (tokens are about 4 bytes)
+    let (stdout_budget, stderr_budget) =
+        split_budget(stdout.len(), stderr.len(), token_budget() * 4);
+    let mut context = format!("Output of `{command}` ({status}):");
+    for (name, stream, max_bytes) in [
+        ("stdout", &stdout, stdout_budget),
+        ("stderr", &stderr, stderr_budget),
+    ] {
+        let stream = String::from_utf8_lossy(stream);
+        if !stream.trim().is_empty() {
+            let stream = truncate(&stream, max_bytes);
+            context.push_str(&format!("\n\n{name}:\n\n{}", fence(Path::new(""), &stream)));
+        }
+    }
+    Ok(context)
+}
+
+#[test]
+fn test_truncate_output() {
+    assert_eq!(truncate("short", 10), "short");
+    assert_eq!(
+        truncate("line 1\nline 2\nline 3\n", 10),
+        "[... 14 bytes omitted]\nline 3\n"
+    );
+}
+
+#[test]
+fn test_split_budget() {
+    assert_eq!(split_budget(100, 100, 100), (50, 50));
+    // A short stream leaves its share to the other one
+    assert_eq!(split_budget(100, 10, 100), (90, 50));
+    assert_eq!(split_budget(10, 100, 100), (50, 90));
+    assert_eq!(split_budget(60, 60, 100), (50, 50));
+}
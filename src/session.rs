@@ -0,0 +1,248 @@
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Local, Utc};
+use crossterm::{execute, terminal::SetTitle};
+use serde::{Deserialize, Serialize};
+
+use crate::client::{Msg, Usage};
+use crate::crypto;
+
+/// A single conversation, as it is stored on disk
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Session {
+    pub id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    /// Id of the session this one was forked from
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub forked_from: Option<String>,
+    pub created: DateTime<Utc>,
+    pub updated: DateTime<Utc>,
+    pub model: String,
+    pub messages: Vec<Msg>,
+    #[serde(default)]
+    pub usage: Usage,
+}
+
+/// Directory where all sessions are stored
+/// (`$XDG_DATA_HOME/rgpt/sessions`, or `~/.local/share/rgpt/sessions`)
+pub fn sessions_dir() -> io::Result<PathBuf> {
+    let data_dir = match env::var_os("XDG_DATA_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => {
+            let home = env::var_os("HOME")
+                .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "HOME is not set"))?;
+            PathBuf::from(home).join(".local").join("share")
+        }
+    };
+    Ok(data_dir.join("rgpt").join("sessions"))
+}
+
+impl Session {
+    pub fn new(model: String) -> Self {
+        let now = Utc::now();
+        Session {
+            // Sortable and still readable, like "20240131-174502-123"
+            id: now
+                .with_timezone(&Local)
+                .format("%Y%m%d-%H%M%S-%3f")
+                .to_string(),
+            title: None,
+            forked_from: None,
+            created: now,
+            updated: now,
+            model,
+            messages: Vec::new(),
+            usage: Usage::default(),
+        }
+    }
+
+    /// Creates a new session, that continues from the current state of this one
+    pub fn fork(&self) -> Session {
+        let mut fork = Session::new(self.model.clone());
+        fork.title = self.title.as_ref().map(|title| format!("{title} (fork)"));
+        fork.forked_from = Some(self.id.clone());
+        fork.messages = self.messages.clone();
+        fork
+    }
+
+    /// Short description of the session (taken from the first question, if it has no title)
+    pub fn title(&self) -> String {
+        if let Some(title) = &self.title {
+            return title.clone();
+        }
+        let Some(question) = self.messages.iter().find(|m| m.role == "user") else {
+            return "(empty)".to_string();
+        };
+        let line = question.content.lines().next().unwrap_or_default().trim();
+        let mut title: String = line.chars().take(60).collect();
+        if line.chars().count() > 60 {
+            title.push('…');
+        }
+        title
+    }
+
+    /// Shows the title of the session in the title bar of the terminal
+    pub fn set_terminal_title(&self) {
+        // Not being able to set the title is not worth bothering anyone
+        let _ = execute!(io::stdout(), SetTitle(format!("rgpt - {}", self.title())));
+    }
+
+    pub fn path(&self) -> io::Result<PathBuf> {
+        Ok(sessions_dir()?.join(format!("{}.json", self.id)))
+    }
+
+    /// Updates the timestamp and writes the session to disk
+    pub fn save(&mut self) -> io::Result<()> {
+        self.updated = Utc::now();
+        self.write()
+    }
+
+    /// Writes the session to disk, without touching its timestamps.
+    ///
+    /// The file is written to a temporary location first and then moved into place,
+    /// so a crash never leaves a half-written session behind.
+    pub fn write(&self) -> io::Result<()> {
+        let path = self.path()?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let tmp = path.with_extension("json.tmp");
+        fs::write(&tmp, crypto::seal(serde_json::to_vec_pretty(self)?)?)?;
+        fs::rename(tmp, path)
+    }
+}
+
+/// Loads all stored sessions, sorted from oldest to newest
+pub fn list() -> io::Result<Vec<Session>> {
+    let dir = sessions_dir()?;
+    if !dir.exists() {
+        return Ok(Vec::new());
+    }
+    let mut sessions = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension().is_some_and(|ext| ext == "json") {
+            match read(&path) {
+                Ok(session) => sessions.push(session),
+                Err(e) => eprintln!("--- System: Skipping {}: {e}", path.display()),
+            }
+        }
+    }
+    sessions.sort_by_key(|s| s.updated);
+    Ok(sessions)
+}
+
+fn read(path: &Path) -> io::Result<Session> {
+    let session = serde_json::from_slice(&crypto::open(fs::read(path)?)?)?;
+    Ok(session)
+}
+
+/// Loads the session with the given id (or the only session whose id starts with it)
+pub fn load(id: &str) -> io::Result<Session> {
+    let path = sessions_dir()?.join(format!("{id}.json"));
+    if path.exists() {
+        return read(&path);
+    }
+    let mut matches: Vec<Session> = list()?
+        .into_iter()
+        .filter(|s| s.id.starts_with(id))
+        .collect();
+    match matches.len() {
+        1 => Ok(matches.remove(0)),
+        0 => Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("No session with id '{id}'"),
+        )),
+        n => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("'{id}' matches {n} sessions - please be more specific"),
+        )),
+    }
+}
+
+/// Whether `id` is the id of a stored session (or the beginning of one), without reading it
+pub fn exists(id: &str) -> bool {
+    let Ok(entries) = sessions_dir().and_then(fs::read_dir) else {
+        return false;
+    };
+    !id.is_empty()
+        && entries.flatten().any(|entry| {
+            entry
+                .file_name()
+                .to_string_lossy()
+                .strip_suffix(".json")
+                .is_some_and(|stored| stored.starts_with(id))
+        })
+}
+
+/// Loads the session that was updated last
+pub fn latest() -> io::Result<Session> {
+    list()?
+        .pop()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "There are no stored sessions"))
+}
+
+/// Rewrites all stored sessions, so that they are encrypted with the configured key
+pub fn encrypt_all() -> Result<(), Box<dyn std::error::Error>> {
+    if !crypto::enabled() {
+        return Err("Set RGPT_KEY_FILE or RGPT_PASSPHRASE to encrypt the sessions".into());
+    }
+    let sessions = list()?;
+    for session in sessions.iter() {
+        session.write()?;
+    }
+    println!("--- System: Encrypted {} sessions", sessions.len());
+    Ok(())
+}
+
+#[test]
+fn test_store_sessions() {
+    // The only test that touches the sessions directory, so nothing else sees the temporary one
+    let data_dir = env::temp_dir().join(format!("rgpt-sessions-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&data_dir);
+    env::set_var("XDG_DATA_HOME", &data_dir);
+
+    let mut first = Session::new("gpt-4o".to_string());
+    first.id = "20240101-120000-000".to_string();
+    first.title = Some("Tokio select".to_string());
+    first
+        .messages
+        .push(Msg::new("user", "How does select! work?".to_string()));
+    first.write().unwrap();
+    let mut second = first.clone();
+    second.id = "20240101-130000-000".to_string();
+    second.updated = first.updated + chrono::Duration::minutes(1);
+    second.write().unwrap();
+
+    let read = read(&first.path().unwrap()).unwrap();
+    assert_eq!(read.id, first.id);
+    assert_eq!(read.title, first.title);
+    assert_eq!(read.model, "gpt-4o");
+    assert_eq!(read.updated, first.updated);
+    assert_eq!(read.messages[0].content, "How does select! work?");
+
+    // Files next to the sessions are no sessions
+    let dir = sessions_dir().unwrap();
+    fs::write(dir.join(".salt"), b"salt").unwrap();
+    fs::write(dir.join(".keycheck"), b"check").unwrap();
+    fs::write(dir.join("20240101-140000-000.json.tmp"), b"{").unwrap();
+    let ids: Vec<String> = list().unwrap().into_iter().map(|s| s.id).collect();
+    assert_eq!(ids, ["20240101-120000-000", "20240101-130000-000"]);
+    assert_eq!(latest().unwrap().id, "20240101-130000-000");
+
+    // A unique prefix is enough, an ambiguous one is refused
+    assert_eq!(load("20240101-13").unwrap().id, "20240101-130000-000");
+    assert_eq!(load("20240101-120000-000").unwrap().id, first.id);
+    let ambiguous = load("20240101").unwrap_err();
+    assert_eq!(ambiguous.kind(), io::ErrorKind::InvalidInput);
+    assert_eq!(load("2025").unwrap_err().kind(), io::ErrorKind::NotFound);
+    assert!(exists("20240101-12"));
+    assert!(!exists("2025"));
+    assert!(!exists(""));
+
+    fs::remove_dir_all(data_dir).unwrap();
+}
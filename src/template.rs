@@ -0,0 +1,120 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::config::config_dir;
+
+/// Directory of the prompt templates (`~/.config/rgpt/templates`)
+fn templates_dir() -> io::Result<PathBuf> {
+    Ok(config_dir()?.join("templates"))
+}
+
+/// Names of all templates, sorted
+pub fn list() -> io::Result<Vec<String>> {
+    let dir = templates_dir()?;
+    if !dir.exists() {
+        return Ok(Vec::new());
+    }
+    let mut names = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension().is_some_and(|ext| ext == "md") {
+            if let Some(name) = path.file_stem() {
+                names.push(name.to_string_lossy().to_string());
+            }
+        }
+    }
+    names.sort();
+    Ok(names)
+}
+
+/// Wraps the content of a file in a fenced code block, with the extension as language
+pub fn fence(path: &Path, content: &str) -> String {
+    let lang = path
+        .extension()
+        .map(|ext| ext.to_string_lossy().to_string())
+        .unwrap_or_default();
+    // The fence has to be longer than any backtick run inside the file
+    let mut longest = 0;
+    let mut run = 0;
+    for c in content.chars() {
+        run = if c == '`' { run + 1 } else { 0 };
+        longest = longest.max(run);
+    }
+    let fence = "`".repeat(longest.max(2) + 1);
+    let newline = if content.ends_with('\n') { "" } else { "\n" };
+    format!("{fence}{lang}\n{content}{newline}{fence}")
+}
+
+/// Fills the placeholders of `template`.
+///
+/// `{{name}}` is replaced by the value of `name`,
+/// `{{@name}}` by the content of the file that `name` points to.
+fn render(template: &str, vars: &HashMap<&str, &str>) -> Result<String, Box<dyn Error>> {
+    let mut output = String::with_capacity(template.len());
+    let mut missing = Vec::new();
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        let Some(end) = rest[start..].find("}}") else {
+            break;
+        };
+        output.push_str(&rest[..start]);
+        let placeholder = rest[start + 2..start + end].trim();
+        let (inline, name) = match placeholder.strip_prefix('@') {
+            Some(name) => (true, name.trim()),
+            None => (false, placeholder),
+        };
+        match vars.get(name) {
+            Some(path) if inline => {
+                let content =
+                    fs::read_to_string(path).map_err(|e| format!("Failed to read {path}: {e}"))?;
+                output.push_str(&fence(Path::new(path), &content));
+            }
+            Some(value) => output.push_str(value),
+            None => {
+                if !missing.contains(&name) {
+                    missing.push(name);
+                }
+            }
+        }
+        rest = &rest[start + end + 2..];
+    }
+    output.push_str(rest);
+    if !missing.is_empty() {
+        return Err(format!("Missing values for {}", missing.join(", ")).into());
+    }
+    Ok(output.trim().to_string())
+}
+
+/// Fills the template `name` with the `key=value` pairs of `args`
+pub fn expand(name: &str, args: &[String]) -> Result<String, Box<dyn Error>> {
+    let path = templates_dir()?.join(format!("{name}.md"));
+    let template = match fs::read_to_string(&path) {
+        Ok(template) => template,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            return Err(format!("Unknown template '{name}' - see '/t'").into())
+        }
+        Err(e) => return Err(format!("Failed to read {}: {e}", path.display()).into()),
+    };
+    let mut vars = HashMap::new();
+    for arg in args {
+        let Some((key, value)) = arg.split_once('=') else {
+            return Err(format!("Invalid argument '{arg}' - expected key=value").into());
+        };
+        vars.insert(key, value);
+    }
+    render(&template, &vars)
+}
+
+#[test]
+fn test_render_template() {
+    let vars = HashMap::from([("lang", "rust"), ("file", "Cargo.toml")]);
+    let rendered = render("Review this {{ lang }} code:\n\n{{@file}}\n", &vars).unwrap();
+    assert!(rendered.starts_with("Review this rust code:\n\n```toml\n[package]"));
+    assert!(rendered.ends_with("\n```"));
+
+    let err = render("{{lang}} {{file}} {{focus}} {{focus}}", &HashMap::new()).unwrap_err();
+    assert_eq!(err.to_string(), "Missing values for lang, file, focus");
+}